@@ -1,6 +1,8 @@
 use std::time;
 
 use winit_gameloop::game_loop;
+use winit_gameloop::scheduler::Stage;
+use winit_gameloop::utility::frame::TargetFrameRate;
 
 use winit::event::Event;
 
@@ -22,17 +24,25 @@ impl SimpleGame {
 }
 
 impl game_loop::Game for SimpleGame {
+    type RenderPacket = ();
+
     fn init(&mut self) {}
 
     fn start(&mut self) {}
 
     fn event<T>(&mut self, _event: &Event<'_, T>) {}
 
-    fn update_fixed_step(&mut self, _time: time::Duration, _dt: time::Duration) {
+    fn update_fixed_step(
+        &mut self,
+        _context: &mut game_loop::GameContext,
+        _tick: game_loop::Tick,
+        _time: time::Duration,
+        _dt: time::Duration,
+    ) {
         //println!("UPDATE {:?} {:?}", t, dt);
     }
 
-    fn update(&mut self, _time: time::Duration) {
+    fn update(&mut self, _context: &mut game_loop::GameContext, _time: time::Duration) {
         //println!("UPDATE {:?} {:?}", t, dt);
     }
 
@@ -40,19 +50,34 @@ impl game_loop::Game for SimpleGame {
         //println!("RENDER {:?} {}", lag, dt);
     }
 
-    fn resized(&mut self) {}
+    fn resized(&mut self, _size: winit::dpi::PhysicalSize<u32>) {}
 
     fn request_redraw(&self) {
         self.window.request_redraw();
     }
 
+    fn window(&self) -> Option<&winit::window::Window> {
+        Some(&self.window)
+    }
+
     fn destroy(&self) {}
 
     fn stats(&self, _game_stats: &game_loop::GameStats) {}
 }
 
 fn main() {
-    let game_loop = game_loop::GameLoop::new();
+    let mut game_loop = game_loop::GameLoop::new();
+    game_loop.set_target_frame_rate(TargetFrameRate::MatchMonitor);
+    // show the frame duration in the title
+    game_loop.scheduler().every(
+        Stage::RedrawEventsCleared,
+        time::Duration::from_millis(250),
+        |task| {
+            if let Some(window) = task.window {
+                window.set_title(&format!("Game Loop ({:?})", task.stats.frame_duration));
+            }
+        },
+    );
 
     let vulkan_app = SimpleGame::new(&game_loop);
     game_loop.run(vulkan_app);
@@ -1,11 +1,12 @@
 pub mod utility;
 
 use std::time;
+use winit_gameloop::action::{ActionMap, Binding};
+use winit_gameloop::display::{DisplayManager, DisplayMode};
 use winit_gameloop::game_loop;
 
 use winit::dpi::PhysicalSize;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
-use winit::window::Fullscreen;
+use winit::event::{Event, VirtualKeyCode};
 
 // the mod define some fixed functions that have been learned before.
 use utility::{constants::*, debug::*, share, structures::*};
@@ -24,6 +25,7 @@ use std::ptr;
 const WINDOW_TITLE: &'static str = "16.Swap Chain Recreation";
 const MODEL_PATH: &'static str = "examples/vulkan/assets/chalet.obj";
 const TEXTURE_PATH: &'static str = "examples/vulkan/assets/chalet.jpg";
+const BINDINGS_PATH: &str = "examples/vulkan/bindings.cfg";
 
 struct VulkanGame {
     window: winit::window::Window,
@@ -93,6 +95,9 @@ struct VulkanGame {
     is_framebuffer_resized: bool,
 
     time: time::Duration,
+
+    actions: ActionMap,
+    display: DisplayManager,
 }
 
 impl VulkanGame {
@@ -323,9 +328,23 @@ impl VulkanGame {
             is_framebuffer_resized: false,
 
             time: time::Duration::from_millis(0),
+
+            actions: ActionMap::load(BINDINGS_PATH).unwrap_or_else(|err| {
+                eprintln!("failed to load {} ({}), using defaults", BINDINGS_PATH, err);
+                VulkanGame::default_actions()
+            }),
+            display: DisplayManager::new(game_loop),
         }
     }
 
+    fn default_actions() -> ActionMap {
+        let mut actions = ActionMap::new();
+        actions.bind("exclusive_fullscreen", Binding::key(VirtualKeyCode::X));
+        actions.bind("toggle_fullscreen", Binding::key(VirtualKeyCode::F));
+        actions.bind("cycle_size", Binding::key(VirtualKeyCode::S));
+        actions
+    }
+
     fn create_texture_image(
         device: &ash::Device,
         command_pool: vk::CommandPool,
@@ -1388,73 +1407,42 @@ impl VulkanGame {
 }
 
 impl game_loop::Game for VulkanGame {
+    type RenderPacket = ();
+
     fn init(&mut self) {}
     fn start(&mut self) {}
 
-    fn event<T>(&mut self, event: &Event<'_, T>) {
-        match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::KeyboardInput { input, .. } => match input {
-                    KeyboardInput {
-                        virtual_keycode,
-                        state,
-                        ..
-                    } => match (virtual_keycode, state) {
-                        (Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
-                            //*control_flow = ControlFlow::Exit
-                        }
-                        (Some(VirtualKeyCode::X), ElementState::Pressed) => {
-                            fn area(size: PhysicalSize<u32>) -> u32 {
-                                size.width * size.height
-                            }
-                            let monitor = self
-                                .window
-                                .current_monitor()
-                                .expect("Failed to get current monitor!");
-                            if let Some(mode) = monitor
-                                .video_modes()
-                                .max_by(|a, b| area(a.size()).cmp(&area(b.size())))
-                            {
-                                self.window
-                                    .set_fullscreen(Some(Fullscreen::Exclusive(mode)));
-                            } else {
-                                eprintln!("no video modes available");
-                            }
-                        }
-                        (Some(VirtualKeyCode::F), ElementState::Pressed) => {
-                            if self.window.fullscreen().is_some() {
-                                self.window.set_fullscreen(None);
-                            } else {
-                                let monitor = self.window.current_monitor();
-                                self.window
-                                    .set_fullscreen(Some(Fullscreen::Borderless(monitor)));
-                            }
-                        }
-                        (Some(VirtualKeyCode::S), ElementState::Pressed) => {
-                            let size = match self.window.inner_size().width {
-                                320 => PhysicalSize::new(640, 480),
-                                640 => PhysicalSize::new(1024, 768),
-                                //1024 => PhysicalSize::new(1280, 1024),
-                                //1280 => PhysicalSize::new(320, 240),
-                                _ => PhysicalSize::new(320, 240),
-                            };
-                            self.window.set_inner_size(size);
-                        }
-                        _ => {}
-                    },
-                },
-                _ => {}
-            },
-            _ => {}
-        }
-    }
+    fn event<T>(&mut self, _event: &Event<'_, T>) {}
 
-    fn update_fixed_step(&mut self, _time: time::Duration, _dt: time::Duration) {
+    fn update_fixed_step(
+        &mut self,
+        context: &mut game_loop::GameContext,
+        _tick: game_loop::Tick,
+        _time: time::Duration,
+        _dt: time::Duration,
+    ) {
+        let input = context.input();
+        if self.actions.is_pressed(input, "exclusive_fullscreen") {
+            self.display.toggle(&self.window, DisplayMode::Exclusive);
+        }
+        if self.actions.is_pressed(input, "toggle_fullscreen") {
+            self.display.toggle(&self.window, DisplayMode::Borderless);
+        }
+        if self.actions.is_pressed(input, "cycle_size") {
+            let size = match self.window.inner_size().width {
+                320 => PhysicalSize::new(640, 480),
+                640 => PhysicalSize::new(1024, 768),
+                //1024 => PhysicalSize::new(1280, 1024),
+                //1280 => PhysicalSize::new(320, 240),
+                _ => PhysicalSize::new(320, 240),
+            };
+            self.window.set_inner_size(size);
+        }
         //println!("UPDATE {:?} {:?}", t, dt);
         //self.time += dt.as_secs_f32();
     }
 
-    fn update(&mut self, time: time::Duration) {
+    fn update(&mut self, _context: &mut game_loop::GameContext, time: time::Duration) {
         //println!("UPDATE {:?} {:?}", t, dt);
         self.time = time;
     }
@@ -1468,7 +1456,7 @@ impl game_loop::Game for VulkanGame {
         //thread::sleep(time::Duration::from_millis(80));
     }
 
-    fn resized(&mut self) {
+    fn resized(&mut self, _size: PhysicalSize<u32>) {
         self.resize_framebuffer();
     }
 
@@ -1477,6 +1465,10 @@ impl game_loop::Game for VulkanGame {
         self.window.request_redraw();
     }
 
+    fn window(&self) -> Option<&winit::window::Window> {
+        Some(&self.window)
+    }
+
     fn destroy(&self) {
         self.wait_device_idle();
     }
@@ -1496,7 +1488,8 @@ fn main() {
     // println!("{}", c);
     // std::process::exit(0);
 
-    let game_loop = game_loop::GameLoop::new();
+    let mut game_loop = game_loop::GameLoop::new();
+    game_loop.persist_window_state("window_state.txt");
 
     let vulkan_app = VulkanGame::new(&game_loop);
     game_loop.run(vulkan_app);
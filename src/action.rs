@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+use crate::input::{self, InputState};
+
+// Button
+
+/// A physical button that can be bound to an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Button {
+    fn is_down(&self, input: &InputState) -> bool {
+        match *self {
+            Button::Key(key) => input.is_key_down(key),
+            Button::Mouse(button) => input.is_button_down(button),
+        }
+    }
+
+    fn is_pressed(&self, input: &InputState) -> bool {
+        match *self {
+            Button::Key(key) => input.is_key_pressed(key),
+            Button::Mouse(button) => input.is_button_pressed(button),
+        }
+    }
+
+    fn is_released(&self, input: &InputState) -> bool {
+        match *self {
+            Button::Key(key) => input.is_key_released(key),
+            Button::Mouse(button) => input.is_button_released(button),
+        }
+    }
+}
+
+// Binding
+
+/// A button, plus the modifiers that must be held for the binding to trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub button: Button,
+    pub modifiers: ModifiersState,
+}
+
+impl Binding {
+    pub fn new(button: Button) -> Binding {
+        Binding {
+            button,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn key(key: VirtualKeyCode) -> Binding {
+        Self::new(Button::Key(key))
+    }
+
+    pub fn mouse(button: MouseButton) -> Binding {
+        Self::new(Button::Mouse(button))
+    }
+
+    pub fn with_modifiers(mut self, modifiers: ModifiersState) -> Binding {
+        self.modifiers = modifiers;
+        self
+    }
+
+    // the binding modifiers are held, and no other command modifier
+    // (so that "S" does not fire on Ctrl+S, but still fires with Shift held)
+    fn modifiers_held(&self, input: &InputState) -> bool {
+        let held = input.modifiers();
+        let command = ModifiersState::CTRL | ModifiersState::ALT | ModifiersState::LOGO;
+        held.contains(self.modifiers) && (held & command) == (self.modifiers & command)
+    }
+}
+
+// AxisBinding
+
+/// A pair of bindings driving an axis towards -1.0 and +1.0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxisBinding {
+    pub negative: Binding,
+    pub positive: Binding,
+}
+
+// ActionMap
+
+/// Maps named actions and axes to (possibly several) bindings.
+///
+/// Edges (`is_pressed`, `is_released`) follow the `InputState` semantics
+/// and should be queried from `Game::update_fixed_step`.
+#[derive(Default)]
+pub struct ActionMap {
+    actions: HashMap<String, Vec<Binding>>,
+    axes: HashMap<String, Vec<AxisBinding>>,
+}
+
+impl ActionMap {
+    pub fn new() -> ActionMap {
+        Self::default()
+    }
+
+    /// Adds a binding to an action (an action can have several bindings).
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Adds a binding to an axis (an axis can have several bindings).
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        let bindings = self.axes.entry(axis.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Removes all the bindings of an action.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    /// Removes all the bindings of an axis.
+    pub fn unbind_axis(&mut self, axis: &str) {
+        self.axes.remove(axis);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn axis_bindings(&self, axis: &str) -> &[AxisBinding] {
+        self.axes.get(axis).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_down(&self, input: &InputState, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|b| b.modifiers_held(input) && b.button.is_down(input))
+    }
+
+    pub fn is_pressed(&self, input: &InputState, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|b| b.modifiers_held(input) && b.button.is_pressed(input))
+    }
+
+    pub fn is_released(&self, input: &InputState, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|b| b.button.is_released(input))
+    }
+
+    /// Returns the axis value, clamped to [-1.0, 1.0].
+    pub fn axis(&self, input: &InputState, axis: &str) -> f32 {
+        let value: f32 = self
+            .axis_bindings(axis)
+            .iter()
+            .map(|b| {
+                let mut value = 0.0;
+                if b.negative.modifiers_held(input) && b.negative.button.is_down(input) {
+                    value -= 1.0;
+                }
+                if b.positive.modifiers_held(input) && b.positive.button.is_down(input) {
+                    value += 1.0;
+                }
+                value
+            })
+            .sum();
+        value.clamp(-1.0, 1.0)
+    }
+
+    /// Loads bindings from a file (see `ActionMap::from_str` for the format).
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ActionMap> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Saves bindings to a file (see `ActionMap::from_str` for the format).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+/// Parses bindings, one per line:
+///
+/// ```text
+/// # comment
+/// action jump = Space
+/// action jump = Mouse:Right
+/// action toggle_fullscreen = Alt+Return
+/// axis move_x = Left, Right
+/// ```
+impl FromStr for ActionMap {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut action_map = ActionMap::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, message),
+                )
+            };
+            let (lhs, rhs) = line.split_once('=').ok_or_else(|| error("missing '='"))?;
+            let (kind, name) = lhs
+                .trim()
+                .split_once(' ')
+                .ok_or_else(|| error("missing action name"))?;
+            let name = name.trim();
+            match kind {
+                "action" => {
+                    let binding = parse_binding(rhs).ok_or_else(|| error("invalid binding"))?;
+                    action_map.bind(name, binding);
+                }
+                "axis" => {
+                    let (negative, positive) =
+                        rhs.split_once(',').ok_or_else(|| error("missing ','"))?;
+                    let negative =
+                        parse_binding(negative).ok_or_else(|| error("invalid binding"))?;
+                    let positive =
+                        parse_binding(positive).ok_or_else(|| error("invalid binding"))?;
+                    action_map.bind_axis(name, AxisBinding { negative, positive });
+                }
+                _ => return Err(error("expected 'action' or 'axis'")),
+            }
+        }
+        Ok(action_map)
+    }
+}
+
+impl fmt::Display for ActionMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // sort for stable output
+        let mut actions: Vec<_> = self.actions.iter().collect();
+        actions.sort_by(|a, b| a.0.cmp(b.0));
+        for (action, bindings) in actions {
+            for binding in bindings {
+                writeln!(f, "action {} = {}", action, BindingDisplay(binding))?;
+            }
+        }
+        let mut axes: Vec<_> = self.axes.iter().collect();
+        axes.sort_by(|a, b| a.0.cmp(b.0));
+        for (axis, bindings) in axes {
+            for binding in bindings {
+                writeln!(
+                    f,
+                    "axis {} = {}, {}",
+                    axis,
+                    BindingDisplay(&binding.negative),
+                    BindingDisplay(&binding.positive)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// binding (de)serialization
+
+const MODIFIERS: [(&str, ModifiersState); 4] = [
+    ("Ctrl", ModifiersState::CTRL),
+    ("Shift", ModifiersState::SHIFT),
+    ("Alt", ModifiersState::ALT),
+    ("Logo", ModifiersState::LOGO),
+];
+
+struct BindingDisplay<'a>(&'a Binding);
+
+impl fmt::Display for BindingDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, modifier) in MODIFIERS.iter() {
+            if self.0.modifiers.contains(*modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match self.0.button {
+            Button::Key(key) => write!(f, "{:?}", key),
+            Button::Mouse(MouseButton::Other(n)) => write!(f, "Mouse:{}", n),
+            Button::Mouse(button) => write!(f, "Mouse:{:?}", button),
+        }
+    }
+}
+
+fn parse_binding(s: &str) -> Option<Binding> {
+    let mut modifiers = ModifiersState::empty();
+    let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+    let button = parts.pop()?;
+    for part in parts {
+        let (_, modifier) = MODIFIERS.iter().find(|(name, _)| *name == part)?;
+        modifiers |= *modifier;
+    }
+    let button = if let Some(button) = button.strip_prefix("Mouse:") {
+        Button::Mouse(match button {
+            "Left" => MouseButton::Left,
+            "Right" => MouseButton::Right,
+            "Middle" => MouseButton::Middle,
+            n => MouseButton::Other(n.parse().ok()?),
+        })
+    } else {
+        Button::Key(input::key_from_name(button)?)
+    };
+    Some(Binding { button, modifiers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{DeviceId, ElementState, Event, KeyboardInput, WindowEvent};
+    use winit::window::WindowId;
+
+    const CONFIG: &str = "\
+# comment
+action jump = Space
+action jump = Mouse:Right
+action save = Ctrl+S
+action toggle_fullscreen = Alt+Return
+action extra = Ctrl+Shift+Mouse:4
+axis move_x = Left, Right
+";
+
+    fn send(input: &mut InputState, event: WindowEvent<'static>) {
+        input.handle_event::<()>(&Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event,
+        });
+    }
+
+    #[allow(deprecated)]
+    fn key(input: &mut InputState, key: VirtualKeyCode, state: ElementState) {
+        let event = WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        };
+        send(input, event);
+    }
+
+    #[test]
+    fn parse_save_parse_round_trip() {
+        let action_map: ActionMap = CONFIG.parse().unwrap();
+        assert_eq!(action_map.bindings("jump").len(), 2);
+        assert_eq!(
+            action_map.bindings("save"),
+            [Binding::key(VirtualKeyCode::S).with_modifiers(ModifiersState::CTRL)]
+        );
+        assert_eq!(
+            action_map.bindings("extra"),
+            [Binding::mouse(MouseButton::Other(4))
+                .with_modifiers(ModifiersState::CTRL | ModifiersState::SHIFT)]
+        );
+        assert_eq!(
+            action_map.axis_bindings("move_x"),
+            [AxisBinding {
+                negative: Binding::key(VirtualKeyCode::Left),
+                positive: Binding::key(VirtualKeyCode::Right),
+            }]
+        );
+
+        let saved = action_map.to_string();
+        let reparsed: ActionMap = saved.parse().unwrap();
+        assert_eq!(reparsed.to_string(), saved);
+        for action in ["jump", "save", "toggle_fullscreen", "extra"] {
+            assert_eq!(reparsed.bindings(action), action_map.bindings(action));
+        }
+        assert_eq!(
+            reparsed.axis_bindings("move_x"),
+            action_map.axis_bindings("move_x")
+        );
+    }
+
+    #[test]
+    fn example_bindings_parse() {
+        let action_map: ActionMap = include_str!("../examples/vulkan/bindings.cfg")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            action_map.bindings("toggle_fullscreen"),
+            [Binding::key(VirtualKeyCode::F)]
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_rejected_with_their_number() {
+        for (config, message) in [
+            ("action jump Space", "line 1: missing '='"),
+            ("\naction = Space", "line 2: missing action name"),
+            ("action jump = Nope", "line 1: invalid binding"),
+            ("action jump = Hyper+Space", "line 1: invalid binding"),
+            ("action jump = Mouse:Fourth", "line 1: invalid binding"),
+            ("axis move_x = Left", "line 1: missing ','"),
+            ("axis move_x = Left, ", "line 1: invalid binding"),
+            ("button jump = Space", "line 1: expected 'action' or 'axis'"),
+        ] {
+            let error = config.parse::<ActionMap>().err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), message, "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn modifiers_must_match() {
+        let mut action_map: ActionMap = CONFIG.parse().unwrap();
+        let mut input = InputState::new();
+
+        // Ctrl+S triggers "save" only
+        action_map.bind("step", Binding::key(VirtualKeyCode::S));
+        send(
+            &mut input,
+            WindowEvent::ModifiersChanged(ModifiersState::CTRL),
+        );
+        key(&mut input, VirtualKeyCode::S, ElementState::Pressed);
+        input.begin_step();
+        assert!(action_map.is_pressed(&input, "save"));
+        assert!(!action_map.is_pressed(&input, "step"));
+        input.end_step();
+
+        // releases ignore the modifiers (they may be released first)
+        send(
+            &mut input,
+            WindowEvent::ModifiersChanged(ModifiersState::empty()),
+        );
+        key(&mut input, VirtualKeyCode::S, ElementState::Released);
+        input.begin_step();
+        assert!(action_map.is_released(&input, "save"));
+        assert!(action_map.is_released(&input, "step"));
+        input.end_step();
+
+        // Shift is not a command modifier: S still fires, Ctrl+S doesn't
+        send(
+            &mut input,
+            WindowEvent::ModifiersChanged(ModifiersState::SHIFT),
+        );
+        key(&mut input, VirtualKeyCode::S, ElementState::Pressed);
+        input.begin_step();
+        assert!(action_map.is_pressed(&input, "step"));
+        assert!(!action_map.is_pressed(&input, "save"));
+        input.end_step();
+    }
+}
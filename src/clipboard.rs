@@ -0,0 +1,30 @@
+/// Clipboard access, so that games can plug in a system clipboard.
+pub trait Clipboard {
+    /// Returns the clipboard contents, if it contains text.
+    fn get(&mut self) -> Option<String>;
+    fn set(&mut self, text: &str);
+}
+
+// MemoryClipboard
+
+/// A clipboard local to the process (the default, also useful for tests).
+#[derive(Default)]
+pub struct MemoryClipboard {
+    contents: Option<String>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> MemoryClipboard {
+        Self::default()
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get(&mut self) -> Option<String> {
+        self.contents.clone()
+    }
+
+    fn set(&mut self, text: &str) {
+        self.contents = Some(text.to_string());
+    }
+}
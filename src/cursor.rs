@@ -0,0 +1,133 @@
+use std::mem;
+
+use winit::event::{DeviceEvent, Event, WindowEvent};
+use winit::window::{CursorGrabMode, Window};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Visible and free to leave the window.
+    #[default]
+    Normal,
+    /// Hidden while over the window.
+    Hidden,
+    /// Visible and confined to the window.
+    Confined,
+    /// Hidden and locked in place, for mouse-look (see `Cursor::look_delta`).
+    Locked,
+}
+
+// Cursor
+
+/// Cursor service: applies the cursor mode to `Game::window`, releases the cursor
+/// when the window loses focus and grabs it again when the focus comes back.
+#[derive(Clone)]
+pub struct Cursor {
+    mode: CursorMode,
+    focused: bool,
+    // mode must be (re)applied to the window
+    dirty: bool,
+    // raw motion accumulated since the last step, and during the current step
+    pending_delta: (f64, f64),
+    step_delta: (f64, f64),
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cursor {
+    pub fn new() -> Cursor {
+        Cursor {
+            mode: CursorMode::Normal,
+            focused: true,
+            dirty: false,
+            pending_delta: (0.0, 0.0),
+            step_delta: (0.0, 0.0),
+        }
+    }
+
+    pub fn mode(&self) -> CursorMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CursorMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.dirty = true;
+            self.pending_delta = (0.0, 0.0);
+        }
+    }
+
+    /// Returns the raw mouse motion of the current fixed step, while the cursor is locked.
+    pub fn look_delta(&self) -> (f64, f64) {
+        self.step_delta
+    }
+
+    pub(crate) fn handle_event<T>(&mut self, event: &Event<'_, T>) {
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                ..
+            } => {
+                self.focused = *focused;
+                self.dirty = true;
+            }
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if self.mode == CursorMode::Locked && self.focused => {
+                self.pending_delta.0 += delta.0;
+                self.pending_delta.1 += delta.1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the raw motion accumulated since the last step (saved with snapshots).
+    pub(crate) fn pending_delta(&self) -> (f64, f64) {
+        self.pending_delta
+    }
+
+    pub(crate) fn set_pending_delta(&mut self, delta: (f64, f64)) {
+        self.pending_delta = delta;
+    }
+
+    pub(crate) fn begin_step(&mut self) {
+        self.step_delta = mem::take(&mut self.pending_delta);
+    }
+
+    pub(crate) fn end_step(&mut self) {
+        self.step_delta = (0.0, 0.0);
+    }
+
+    /// Applies the cursor mode to the window (released while unfocused).
+    pub(crate) fn apply(&mut self, window: &Window) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let mode = if self.focused {
+            self.mode
+        } else {
+            CursorMode::Normal
+        };
+        let (grab, visible) = match mode {
+            CursorMode::Normal => (CursorGrabMode::None, true),
+            CursorMode::Hidden => (CursorGrabMode::None, false),
+            CursorMode::Confined => (CursorGrabMode::Confined, true),
+            CursorMode::Locked => (CursorGrabMode::Locked, false),
+        };
+        // not all platforms support all grab modes, fall back to the other one
+        let result = window.set_cursor_grab(grab).or_else(|e| match grab {
+            CursorGrabMode::Locked => window.set_cursor_grab(CursorGrabMode::Confined),
+            CursorGrabMode::Confined => window.set_cursor_grab(CursorGrabMode::Locked),
+            CursorGrabMode::None => Err(e),
+        });
+        if let Err(e) = result {
+            eprintln!("failed to set cursor grab mode {:?} ({})", grab, e);
+        }
+        window.set_cursor_visible(visible);
+    }
+}
@@ -0,0 +1,164 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window};
+
+use crate::game_loop::GameLoop;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+// DisplayManager
+
+/// Enumerates monitors and video modes, and switches windows between
+/// windowed, borderless fullscreen and exclusive fullscreen.
+///
+/// The windowed geometry is saved when leaving windowed mode
+/// and restored when going back to it.
+pub struct DisplayManager {
+    monitors: Vec<MonitorHandle>,
+    primary_monitor: Option<MonitorHandle>,
+    // windowed geometry to restore
+    windowed_position: Option<PhysicalPosition<i32>>,
+    windowed_size: Option<PhysicalSize<u32>>,
+}
+
+impl DisplayManager {
+    pub fn new<T>(game_loop: &GameLoop<T>) -> DisplayManager {
+        DisplayManager {
+            monitors: game_loop.available_monitors().collect(),
+            primary_monitor: game_loop.primary_monitor(),
+            windowed_position: None,
+            windowed_size: None,
+        }
+    }
+
+    pub fn monitors(&self) -> &[MonitorHandle] {
+        &self.monitors
+    }
+
+    pub fn primary_monitor(&self) -> Option<&MonitorHandle> {
+        self.primary_monitor.as_ref()
+    }
+
+    /// Enumerates the monitors again (after a monitor was plugged or unplugged).
+    pub fn refresh_monitors(&mut self, window: &Window) {
+        self.monitors = window.available_monitors().collect();
+        self.primary_monitor = window.primary_monitor();
+    }
+
+    /// Returns the video modes of a monitor, largest and fastest first.
+    pub fn video_modes(monitor: &MonitorHandle) -> Vec<VideoMode> {
+        let mut modes: Vec<VideoMode> = monitor.video_modes().collect();
+        modes.sort_by(|a, b| {
+            area(b.size())
+                .cmp(&area(a.size()))
+                .then(
+                    b.refresh_rate_millihertz()
+                        .cmp(&a.refresh_rate_millihertz()),
+                )
+                .then(b.bit_depth().cmp(&a.bit_depth()))
+        });
+        modes
+    }
+
+    /// Returns the video mode closest to the requested resolution and refresh rate.
+    ///
+    /// The resolution is matched first, then the refresh rate
+    /// (the highest one if no refresh rate is requested).
+    pub fn find_video_mode(
+        monitor: &MonitorHandle,
+        size: PhysicalSize<u32>,
+        refresh_rate_millihertz: Option<u32>,
+    ) -> Option<VideoMode> {
+        fn distance(a: u32, b: u32) -> u64 {
+            (i64::from(a) - i64::from(b)).unsigned_abs()
+        }
+        Self::video_modes(monitor).into_iter().min_by_key(|mode| {
+            let size_distance =
+                distance(mode.size().width, size.width) + distance(mode.size().height, size.height);
+            let refresh_rate_distance = match refresh_rate_millihertz {
+                Some(refresh_rate) => distance(mode.refresh_rate_millihertz(), refresh_rate),
+                None => u64::from(u32::MAX - mode.refresh_rate_millihertz()),
+            };
+            (size_distance, refresh_rate_distance)
+        })
+    }
+
+    /// Returns the largest video mode of a monitor.
+    pub fn largest_video_mode(monitor: &MonitorHandle) -> Option<VideoMode> {
+        Self::video_modes(monitor).into_iter().next()
+    }
+
+    pub fn display_mode(window: &Window) -> DisplayMode {
+        match window.fullscreen() {
+            None => DisplayMode::Windowed,
+            Some(Fullscreen::Borderless(_)) => DisplayMode::Borderless,
+            Some(Fullscreen::Exclusive(_)) => DisplayMode::Exclusive,
+        }
+    }
+
+    /// Goes back to windowed mode, restoring the saved geometry.
+    pub fn set_windowed(&mut self, window: &Window) {
+        if window.fullscreen().is_none() {
+            return;
+        }
+        window.set_fullscreen(None);
+        if let Some(size) = self.windowed_size.take() {
+            window.set_inner_size(size);
+        }
+        if let Some(position) = self.windowed_position.take() {
+            window.set_outer_position(position);
+        }
+    }
+
+    /// Goes borderless fullscreen on the given monitor (the current one if `None`).
+    pub fn set_borderless(&mut self, window: &Window, monitor: Option<MonitorHandle>) {
+        self.save_windowed_geometry(window);
+        let monitor = monitor.or_else(|| window.current_monitor());
+        window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    }
+
+    /// Goes exclusive fullscreen with the given video mode.
+    pub fn set_exclusive(&mut self, window: &Window, mode: VideoMode) {
+        self.save_windowed_geometry(window);
+        window.set_fullscreen(Some(Fullscreen::Exclusive(mode)));
+    }
+
+    /// Switches between windowed and the given mode.
+    pub fn toggle(&mut self, window: &Window, mode: DisplayMode) {
+        if Self::display_mode(window) == mode {
+            self.set_windowed(window);
+            return;
+        }
+        match mode {
+            DisplayMode::Windowed => self.set_windowed(window),
+            DisplayMode::Borderless => self.set_borderless(window, None),
+            DisplayMode::Exclusive => {
+                match window
+                    .current_monitor()
+                    .as_ref()
+                    .and_then(Self::largest_video_mode)
+                {
+                    Some(video_mode) => self.set_exclusive(window, video_mode),
+                    None => eprintln!("no video modes available"),
+                }
+            }
+        }
+    }
+
+    fn save_windowed_geometry(&mut self, window: &Window) {
+        if window.fullscreen().is_some() {
+            return;
+        }
+        self.windowed_position = window.outer_position().ok();
+        self.windowed_size = Some(window.inner_size());
+    }
+}
+
+fn area(size: PhysicalSize<u32>) -> u32 {
+    size.width * size.height
+}
@@ -0,0 +1,299 @@
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time;
+
+use hecs::World;
+use winit::dpi::PhysicalSize;
+use winit::event::Event;
+
+use crate::game_loop::{Game, GameContext, GameStats, Tick};
+use crate::input::InputState;
+
+/// A system, run on the world and the resources.
+pub type System = Box<dyn FnMut(&mut World, &mut Resources)>;
+
+// Resources
+
+/// Global data shared by the systems, one value per type.
+///
+/// The adapter keeps the `InputState`, `GameStats`, `FixedTime`, `FrameTime`
+/// and `WindowSize` resources up to date.
+#[derive(Default)]
+pub struct Resources {
+    resources: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Resources {
+    pub fn new() -> Resources {
+        Self::default()
+    }
+
+    /// Inserts a resource, returns the previous one of the same type.
+    pub fn insert<T: 'static>(&mut self, resource: T) -> Option<T> {
+        self.resources
+            .insert(TypeId::of::<T>(), Box::new(resource))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast().ok())
+            .map(|resource| *resource)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.resources.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.resources.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+}
+
+/// Time of the current fixed step.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedTime {
+    pub tick: Tick,
+    pub time: time::Duration,
+    pub dt: time::Duration,
+}
+
+/// Time of the current frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTime {
+    pub time: time::Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WindowSize(pub PhysicalSize<u32>);
+
+// Schedule
+
+/// Systems run in insertion order.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<System>,
+}
+
+impl Schedule {
+    pub fn new() -> Schedule {
+        Self::default()
+    }
+
+    pub fn add_system<F>(&mut self, system: F)
+    where
+        F: FnMut(&mut World, &mut Resources) + 'static,
+    {
+        self.systems.push(Box::new(system));
+    }
+
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+
+    pub fn run(&mut self, world: &mut World, resources: &mut Resources) {
+        for system in &mut self.systems {
+            system(world, resources);
+        }
+    }
+}
+
+// EcsGame
+
+/// A `Game` built from a `hecs` world and schedules.
+///
+/// The fixed schedule runs from `update_fixed_step`, the variable schedule from `update`
+/// and the render schedule, that extracts render data from the world, from `render`.
+pub struct EcsGame {
+    window: winit::window::Window,
+    world: World,
+    resources: Resources,
+    fixed_schedule: Schedule,
+    schedule: Schedule,
+    render_schedule: Schedule,
+    // stats are given by reference, they are moved to the resources on the next update
+    stats: Cell<Option<GameStats>>,
+}
+
+impl EcsGame {
+    pub fn new(window: winit::window::Window) -> EcsGame {
+        let mut resources = Resources::new();
+        resources.insert(InputState::new());
+        resources.insert(GameStats::default());
+        resources.insert(FixedTime::default());
+        resources.insert(FrameTime::default());
+        resources.insert(WindowSize(window.inner_size()));
+        EcsGame {
+            window,
+            world: World::new(),
+            resources,
+            fixed_schedule: Schedule::new(),
+            schedule: Schedule::new(),
+            render_schedule: Schedule::new(),
+            stats: Cell::new(None),
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    /// Adds a system run on each fixed step.
+    pub fn add_fixed_system<F>(&mut self, system: F)
+    where
+        F: FnMut(&mut World, &mut Resources) + 'static,
+    {
+        self.fixed_schedule.add_system(system);
+    }
+
+    /// Adds a system run once per frame, before rendering.
+    pub fn add_system<F>(&mut self, system: F)
+    where
+        F: FnMut(&mut World, &mut Resources) + 'static,
+    {
+        self.schedule.add_system(system);
+    }
+
+    /// Adds a system run when rendering.
+    pub fn add_render_system<F>(&mut self, system: F)
+    where
+        F: FnMut(&mut World, &mut Resources) + 'static,
+    {
+        self.render_schedule.add_system(system);
+    }
+
+    fn update_resources(&mut self, context: &GameContext) {
+        self.resources.insert(context.input().clone());
+        if let Some(stats) = self.stats.take() {
+            self.resources.insert(stats);
+        }
+    }
+}
+
+impl Game for EcsGame {
+    type RenderPacket = ();
+
+    fn init(&mut self) {}
+
+    fn start(&mut self) {}
+
+    fn event<T>(&mut self, _event: &Event<'_, T>) {}
+
+    fn update_fixed_step(
+        &mut self,
+        context: &mut GameContext,
+        tick: Tick,
+        time: time::Duration,
+        dt: time::Duration,
+    ) {
+        self.update_resources(context);
+        self.resources.insert(FixedTime { tick, time, dt });
+        self.fixed_schedule
+            .run(&mut self.world, &mut self.resources);
+    }
+
+    fn update(&mut self, context: &mut GameContext, time: time::Duration) {
+        self.update_resources(context);
+        self.resources.insert(FrameTime { time });
+        self.schedule.run(&mut self.world, &mut self.resources);
+    }
+
+    fn render(&mut self) {
+        self.render_schedule
+            .run(&mut self.world, &mut self.resources);
+    }
+
+    fn resized(&mut self, size: PhysicalSize<u32>) {
+        self.resources.insert(WindowSize(size));
+    }
+
+    fn request_redraw(&self) {
+        self.window.request_redraw();
+    }
+
+    fn window(&self) -> Option<&winit::window::Window> {
+        Some(&self.window)
+    }
+
+    fn destroy(&self) {}
+
+    fn stats(&self, game_stats: &GameStats) {
+        self.stats.set(Some(*game_stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resources_hold_one_value_per_type() {
+        let mut resources = Resources::new();
+        assert!(!resources.contains::<FrameTime>());
+        assert_eq!(
+            resources
+                .insert(WindowSize(PhysicalSize::new(800, 600)))
+                .map(|s| s.0),
+            None
+        );
+        resources.insert(7u32);
+        let previous = resources.insert(WindowSize(PhysicalSize::new(1024, 768)));
+        assert_eq!(previous.map(|s| s.0), Some(PhysicalSize::new(800, 600)));
+        assert_eq!(resources.get::<WindowSize>().unwrap().0.width, 1024);
+
+        *resources.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(resources.get::<u32>(), Some(&8));
+        assert_eq!(resources.get::<u64>(), None);
+        assert_eq!(resources.remove::<u32>(), Some(8));
+        assert!(!resources.contains::<u32>());
+        assert!(resources.contains::<WindowSize>());
+    }
+
+    #[test]
+    fn schedule_runs_systems_in_order() {
+        let mut world = World::new();
+        let mut resources = Resources::new();
+        resources.insert(Vec::<&str>::new());
+        let mut schedule = Schedule::new();
+        assert!(schedule.is_empty());
+        schedule.add_system(|world, _| {
+            world.spawn((1u32,));
+        });
+        schedule.add_system(|world, resources| {
+            let count = world.query_mut::<&u32>().into_iter().count();
+            resources
+                .get_mut::<Vec<&str>>()
+                .unwrap()
+                .push(if count == 1 { "spawned" } else { "missing" });
+        });
+        schedule.add_system(|_, resources| {
+            resources.get_mut::<Vec<&str>>().unwrap().push("last");
+        });
+        assert_eq!(schedule.len(), 3);
+        schedule.run(&mut world, &mut resources);
+        assert_eq!(resources.get::<Vec<&str>>().unwrap(), &["spawned", "last"]);
+    }
+}
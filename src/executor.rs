@@ -0,0 +1,394 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time;
+
+use winit::event_loop::EventLoopProxy;
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Identifies a spawned task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+// TaskWaker
+
+struct TaskWaker {
+    id: TaskId,
+    ready: Arc<Mutex<VecDeque<TaskId>>>,
+    // true while the task is in the ready queue
+    queued: AtomicBool,
+    proxy: Option<Arc<Mutex<EventLoopProxy<()>>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        lock(&self.ready).push_back(self.id);
+        // wake the event loop up (wakers can be called from other threads)
+        if let Some(proxy) = &self.proxy {
+            let _ = lock(proxy).send_event(());
+        }
+    }
+}
+
+// Clock
+
+// futures waiting for a counter value, one slot per future
+#[derive(Default)]
+struct Waiters {
+    slots: HashMap<u64, (u64, Waker)>,
+    next_slot: u64,
+}
+
+impl Waiters {
+    // registers (or updates) the slot of a future waiting for `due`
+    fn wait(&mut self, slot: &mut Option<u64>, due: u64, waker: &Waker) {
+        let next_slot = &mut self.next_slot;
+        let key = *slot.get_or_insert_with(|| {
+            *next_slot += 1;
+            *next_slot
+        });
+        match self.slots.get_mut(&key) {
+            Some((_, slot_waker)) if slot_waker.will_wake(waker) => {}
+            _ => {
+                self.slots.insert(key, (due, waker.clone()));
+            }
+        }
+    }
+
+    fn remove(&mut self, slot: Option<u64>) {
+        if let Some(key) = slot {
+            self.slots.remove(&key);
+        }
+    }
+
+    fn wake_due(&mut self, now: u64) {
+        self.slots.retain(|_, (due, waker)| {
+            if *due <= now {
+                waker.wake_by_ref();
+            }
+            *due > now
+        });
+    }
+}
+
+// frame and tick counters the futures wait on
+#[derive(Default)]
+struct Clock {
+    frame: u64,
+    tick: u64,
+    frame_waiters: Waiters,
+    tick_waiters: Waiters,
+}
+
+// Executor
+
+/// A single-threaded executor, polled by the game loop once per frame within a time budget.
+///
+/// Tasks run on the event loop thread; wakers post a user event to wake the loop up.
+pub struct Executor {
+    tasks: HashMap<TaskId, (Task, Arc<TaskWaker>)>,
+    ready: Arc<Mutex<VecDeque<TaskId>>>,
+    clock: Arc<Mutex<Clock>>,
+    proxy: Option<Arc<Mutex<EventLoopProxy<()>>>>,
+    next_id: u64,
+}
+
+impl Executor {
+    pub(crate) fn new(proxy: Option<EventLoopProxy<()>>) -> Executor {
+        Executor {
+            tasks: HashMap::new(),
+            ready: Arc::new(Mutex::new(VecDeque::new())),
+            clock: Arc::new(Mutex::new(Clock::default())),
+            proxy: proxy.map(|proxy| Arc::new(Mutex::new(proxy))),
+            next_id: 0,
+        }
+    }
+
+    /// Spawns a future that does not need to be `Send` (it always runs on the loop thread).
+    pub fn spawn_local<F>(&mut self, future: F) -> TaskId
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        let waker = Arc::new(TaskWaker {
+            id,
+            ready: self.ready.clone(),
+            queued: AtomicBool::new(false),
+            proxy: self.proxy.clone(),
+        });
+        // polled for the first time on the next poll
+        waker.wake_by_ref();
+        self.tasks.insert(id, (Box::pin(future), waker));
+        id
+    }
+
+    /// Drops a task, returns false if it already completed (or was cancelled).
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        self.tasks.remove(&id).is_some()
+    }
+
+    pub fn is_running(&self, id: TaskId) -> bool {
+        self.tasks.contains_key(&id)
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Completes on the next frame.
+    pub fn next_frame(&self) -> NextFrame {
+        NextFrame {
+            clock: self.clock.clone(),
+            frame: None,
+            slot: None,
+        }
+    }
+
+    /// Completes after `n` fixed steps.
+    pub fn sleep_ticks(&self, n: u64) -> SleepTicks {
+        SleepTicks {
+            clock: self.clock.clone(),
+            tick: lock(&self.clock).tick + n,
+            slot: None,
+        }
+    }
+
+    /// Called after each fixed step.
+    pub(crate) fn tick(&mut self) {
+        let mut clock = lock(&self.clock);
+        clock.tick += 1;
+        let tick = clock.tick;
+        clock.tick_waiters.wake_due(tick);
+    }
+
+    /// Polls the ready tasks until the budget is exhausted
+    /// (the others are polled on the next frame).
+    pub(crate) fn poll(&mut self, new_frame: bool, budget: time::Duration) {
+        if new_frame {
+            let mut clock = lock(&self.clock);
+            clock.frame += 1;
+            let frame = clock.frame;
+            clock.frame_waiters.wake_due(frame);
+        }
+        let start_time = time::Instant::now();
+        loop {
+            if start_time.elapsed() >= budget {
+                break;
+            }
+            let id = match lock(&self.ready).pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            let (task, waker) = match self.tasks.get_mut(&id) {
+                Some(task) => task,
+                // cancelled
+                None => continue,
+            };
+            waker.queued.store(false, Ordering::Release);
+            let task_waker = Waker::from(waker.clone());
+            let mut context = Context::from_waker(&task_waker);
+            if task.as_mut().poll(&mut context).is_ready() {
+                self.tasks.remove(&id);
+            }
+        }
+    }
+
+    /// Returns true if tasks are ready to be polled.
+    pub(crate) fn has_ready(&self) -> bool {
+        !lock(&self.ready).is_empty()
+    }
+}
+
+// NextFrame
+
+pub struct NextFrame {
+    clock: Arc<Mutex<Clock>>,
+    frame: Option<u64>,
+    slot: Option<u64>,
+}
+
+impl Future for NextFrame {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut clock = lock(&this.clock);
+        // the frame is counted from the first poll
+        let frame = *this.frame.get_or_insert(clock.frame + 1);
+        if clock.frame >= frame {
+            return Poll::Ready(());
+        }
+        clock
+            .frame_waiters
+            .wait(&mut this.slot, frame, context.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for NextFrame {
+    fn drop(&mut self) {
+        lock(&self.clock).frame_waiters.remove(self.slot);
+    }
+}
+
+// SleepTicks
+
+pub struct SleepTicks {
+    clock: Arc<Mutex<Clock>>,
+    tick: u64,
+    slot: Option<u64>,
+}
+
+impl Future for SleepTicks {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut clock = lock(&this.clock);
+        if clock.tick >= this.tick {
+            return Poll::Ready(());
+        }
+        clock
+            .tick_waiters
+            .wait(&mut this.slot, this.tick, context.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for SleepTicks {
+    fn drop(&mut self) {
+        lock(&self.clock).tick_waiters.remove(self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::thread;
+
+    const BUDGET: time::Duration = time::Duration::from_secs(1);
+
+    // polls the future twice on each poll
+    struct PollTwice<F>(Pin<Box<F>>);
+
+    impl<F: Future<Output = ()>> Future for PollTwice<F> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+            match self.0.as_mut().poll(context) {
+                Poll::Ready(()) => Poll::Ready(()),
+                Poll::Pending => self.0.as_mut().poll(context),
+            }
+        }
+    }
+
+    fn waiter_counts(executor: &Executor) -> (usize, usize) {
+        let clock = lock(&executor.clock);
+        (
+            clock.frame_waiters.slots.len(),
+            clock.tick_waiters.slots.len(),
+        )
+    }
+
+    #[test]
+    fn next_frame_completes_on_the_next_frame() {
+        let mut executor = Executor::new(None);
+        let done = Rc::new(Cell::new(false));
+        let next_frame = executor.next_frame();
+        let task_done = done.clone();
+        let id = executor.spawn_local(async move {
+            PollTwice(Box::pin(next_frame)).await;
+            task_done.set(true);
+        });
+        executor.poll(false, BUDGET);
+        assert!(!done.get());
+        // a single waiter, however many times the future is polled
+        assert_eq!(waiter_counts(&executor), (1, 0));
+        executor.poll(true, BUDGET);
+        assert!(done.get());
+        assert!(!executor.is_running(id));
+        assert_eq!(waiter_counts(&executor), (0, 0));
+    }
+
+    #[test]
+    fn sleep_ticks_completes_after_the_ticks() {
+        let mut executor = Executor::new(None);
+        let done = Rc::new(Cell::new(0));
+        for n in 0..3 {
+            let sleep = executor.sleep_ticks(n);
+            let done = done.clone();
+            executor.spawn_local(async move {
+                PollTwice(Box::pin(sleep)).await;
+                done.set(done.get() + 1);
+            });
+        }
+        executor.poll(true, BUDGET);
+        assert_eq!(done.get(), 1);
+        executor.poll(false, BUDGET);
+        assert_eq!(waiter_counts(&executor), (0, 2));
+        executor.tick();
+        executor.poll(false, BUDGET);
+        assert_eq!(done.get(), 2);
+        executor.tick();
+        executor.poll(false, BUDGET);
+        assert_eq!(done.get(), 3);
+        assert_eq!(executor.task_count(), 0);
+        assert_eq!(waiter_counts(&executor), (0, 0));
+    }
+
+    #[test]
+    fn cancelled_tasks_leave_no_waiter() {
+        let mut executor = Executor::new(None);
+        let sleep = executor.sleep_ticks(10);
+        let next_frame = executor.next_frame();
+        let id = executor.spawn_local(async move {
+            sleep.await;
+            next_frame.await;
+        });
+        executor.poll(false, BUDGET);
+        assert_eq!(waiter_counts(&executor), (0, 1));
+        assert!(executor.cancel(id));
+        assert_eq!(waiter_counts(&executor), (0, 0));
+        assert!(!executor.cancel(id));
+    }
+
+    #[test]
+    fn poll_stops_at_the_budget() {
+        let mut executor = Executor::new(None);
+        let done = Rc::new(Cell::new(0));
+        for _ in 0..10 {
+            let done = done.clone();
+            executor.spawn_local(async move {
+                thread::sleep(time::Duration::from_millis(5));
+                done.set(done.get() + 1);
+            });
+        }
+        executor.poll(true, time::Duration::from_secs(0));
+        assert_eq!(done.get(), 0);
+        assert!(executor.has_ready());
+        executor.poll(true, time::Duration::from_millis(12));
+        assert!(done.get() >= 1 && done.get() < 10);
+        assert!(executor.has_ready());
+        executor.poll(true, BUDGET);
+        assert_eq!(done.get(), 10);
+        assert!(!executor.has_ready());
+    }
+}
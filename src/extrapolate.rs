@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time;
+
+// Extrapolate
+
+/// A value that can be moved forward in time at a constant velocity.
+pub trait Extrapolate: Copy {
+    /// Returns the value after moving at `velocity` (per second) during `dt` seconds.
+    fn extrapolate(&self, velocity: &Self, dt: f32) -> Self;
+}
+
+impl Extrapolate for f32 {
+    fn extrapolate(&self, velocity: &Self, dt: f32) -> Self {
+        self + velocity * dt
+    }
+}
+
+impl<const N: usize> Extrapolate for [f32; N] {
+    fn extrapolate(&self, velocity: &Self, dt: f32) -> Self {
+        let mut value = *self;
+        for (v, dv) in value.iter_mut().zip(velocity.iter()) {
+            *v += dv * dt;
+        }
+        value
+    }
+}
+
+// Transform
+
+/// A position and a rotation (Euler angles, in radians).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub position: [f32; 3],
+    pub rotation: [f32; 3],
+}
+
+impl Extrapolate for Transform {
+    fn extrapolate(&self, velocity: &Self, dt: f32) -> Self {
+        Transform {
+            position: self.position.extrapolate(&velocity.position, dt),
+            rotation: self.rotation.extrapolate(&velocity.rotation, dt),
+        }
+    }
+}
+
+// Extrapolator
+
+/// Stores the value and velocity of entities at the last fixed step,
+/// and yields their values extrapolated to the time of rendering.
+///
+/// Values are set from `Game::update_fixed_step` and read from
+/// `Game::render_extrapolated`, with the elapsed time it receives.
+pub struct Extrapolator<K, T> {
+    entities: HashMap<K, (T, T)>,
+    max_elapsed: Option<time::Duration>,
+}
+
+impl<K: Eq + Hash, T: Extrapolate> Extrapolator<K, T> {
+    pub fn new() -> Extrapolator<K, T> {
+        Extrapolator {
+            entities: HashMap::new(),
+            max_elapsed: None,
+        }
+    }
+
+    /// Limits how far values are extrapolated (i.e. on hitches).
+    pub fn set_max_elapsed(&mut self, max_elapsed: Option<time::Duration>) {
+        self.max_elapsed = max_elapsed;
+    }
+
+    /// Sets the value and velocity (per second) of an entity at the last fixed step.
+    pub fn set(&mut self, key: K, value: T, velocity: T) {
+        self.entities.insert(key, (value, velocity));
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.entities.remove(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+    }
+
+    /// Returns the value of an entity at the last fixed step.
+    pub fn value(&self, key: &K) -> Option<T> {
+        self.entities.get(key).map(|(value, _)| *value)
+    }
+
+    pub fn velocity(&self, key: &K) -> Option<T> {
+        self.entities.get(key).map(|(_, velocity)| *velocity)
+    }
+
+    /// Returns the value of an entity, `elapsed` after the last fixed step.
+    pub fn extrapolated(&self, key: &K, elapsed: time::Duration) -> Option<T> {
+        let dt = self.dt(elapsed);
+        self.entities
+            .get(key)
+            .map(|(value, velocity)| value.extrapolate(velocity, dt))
+    }
+
+    /// Iterates over the values of all entities, `elapsed` after the last fixed step.
+    pub fn iter_extrapolated(&self, elapsed: time::Duration) -> impl Iterator<Item = (&K, T)> {
+        let dt = self.dt(elapsed);
+        self.entities
+            .iter()
+            .map(move |(key, (value, velocity))| (key, value.extrapolate(velocity, dt)))
+    }
+
+    fn dt(&self, elapsed: time::Duration) -> f32 {
+        match self.max_elapsed {
+            Some(max_elapsed) => elapsed.min(max_elapsed),
+            None => elapsed,
+        }
+        .as_secs_f32()
+    }
+}
+
+impl<K: Eq + Hash, T: Extrapolate> Default for Extrapolator<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
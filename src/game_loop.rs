@@ -1,28 +1,117 @@
-use std::{thread, time};
+use std::path::Path;
+use std::{cmp, io, mem, thread, time};
 
+use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, Event, KeyboardInput, StartCause, VirtualKeyCode, WindowEvent};
 use winit::event_loop::ControlFlow;
 use winit::monitor::MonitorHandle;
 
+use crate::clipboard;
+use crate::cursor;
+use crate::executor;
+use crate::gamepad;
+use crate::input;
+use crate::jobs;
+use crate::render_thread;
+use crate::replay;
+use crate::scheduler::{self, Stage};
+use crate::simulation;
+use crate::snapshot::{self, Snapshot};
+use crate::text_input;
+use crate::timers;
 use crate::utility::frame;
+use crate::utility::resize;
+use crate::utility::step;
+use crate::window_state;
 //use crate::utility::timer;
 
+// time waited for lockstep inputs is made up for up to this many update periods
+const MAX_LOCKSTEP_LAG: u32 = 8;
+
 pub trait Game {
+    /// The packet rendered on the render thread (see `GameLoop::set_render_pipeline`),
+    /// `()` when the render pipeline is not used.
+    type RenderPacket: Send + 'static;
+
     fn init(&mut self);
     fn start(&mut self);
     fn event<T>(&mut self, event: &Event<'_, T>);
-    fn update_fixed_step(&mut self, time: time::Duration, dt: time::Duration);
-    fn update(&mut self, time: time::Duration);
+    fn update_fixed_step(
+        &mut self,
+        context: &mut GameContext,
+        tick: Tick,
+        time: time::Duration,
+        dt: time::Duration,
+    );
+    fn update(&mut self, context: &mut GameContext, time: time::Duration);
     fn render(&mut self);
-    fn resized(&mut self);
+    /// Called instead of `render` when extrapolation is enabled
+    /// (see `GameLoop::set_extrapolation`), with the time elapsed since the last fixed step.
+    fn render_extrapolated(&mut self, _elapsed: time::Duration) {
+        self.render();
+    }
+    /// Called instead of `render` when the render pipeline is enabled
+    /// (see `GameLoop::set_render_pipeline`): returns the packet rendered on the render
+    /// thread, a frame behind (`None` to skip the frame).
+    fn render_packet(&mut self) -> Option<Self::RenderPacket> {
+        None
+    }
+    fn resized(&mut self, size: PhysicalSize<u32>);
+    /// Called when the application is suspended.
+    /// On some platforms (Android) the window surface is lost and must be destroyed here.
+    fn suspended(&mut self) {}
+    /// Called when the application is resumed (including on startup).
+    /// On some platforms (Android) the window surface must be (re)created here.
+    fn resumed(&mut self) {}
+    /// Called for gamepad events (including connections and disconnections),
+    /// before the fixed updates of the frame.
+    fn gamepad(&mut self, _event: &gamepad::GamepadEvent) {}
+    /// Called when the adaptive update period changes,
+    /// so that the game can adjust its physics parameters.
+    fn update_period_changed(&mut self, _update_period: time::Duration) {}
+    /// Called when a replay reaches the tick its recording ended on
+    /// (live input is handled again from then on).
+    fn replay_finished(&mut self, _tick: Tick) {}
+    /// Called before each fixed step when lockstep is enabled (see `GameLoop::set_lockstep`):
+    /// returns false to delay the step until the inputs of all players are known
+    /// (see `lockstep::LockstepSession::is_ready`).
+    fn lockstep_ready(&mut self, _context: &mut GameContext, _tick: Tick) -> bool {
+        true
+    }
+    /// Called for timers that fire (see `GameContext::timers`),
+    /// at the beginning of the fixed step, before `update_fixed_step`.
+    fn timer(&mut self, _context: &mut GameContext, _timer: timers::TimerId) {}
+    /// Returns the game state to capture and restore when snapshots are enabled
+    /// (see `GameLoop::set_snapshots`).
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshot> {
+        None
+    }
     fn request_redraw(&self);
+    /// Returns the game window, used by the loop services that act on it (IME, ...).
+    fn window(&self) -> Option<&winit::window::Window> {
+        None
+    }
     fn destroy(&self);
     fn stats(&self, game_stats: &GameStats);
 }
 
+#[derive(Clone, Copy)]
 struct GameSetup {
     update_period: time::Duration,
     redraw_on_resize: bool,
+    resize_policy: resize::ResizePolicy,
+    target_frame_rate: frame::TargetFrameRate,
+    // adaptive update period bounds
+    adaptive_update_period: Option<(time::Duration, time::Duration)>,
+    extrapolation: bool,
+    // fixed steps gated by Game::lockstep_ready
+    lockstep: bool,
+    // number of job worker threads
+    job_threads: usize,
+    // time given to async tasks each frame
+    async_budget: time::Duration,
+    // snapshot interval (in ticks) and capacity
+    snapshots: Option<(u64, usize)>,
     // debugging
     lag_time: Option<time::Duration>,
 }
@@ -33,17 +122,30 @@ struct GameState {
     last_frame_time: Option<time::Instant>,
     time: time::Duration,
     accumulator: time::Duration,
+    // number of fixed steps run so far
+    tick: u64,
     // loop
     loop_start_time: time::Instant,
 }
 
 // split frame and globla stats
+#[derive(Clone, Copy, Default)]
 pub struct GameStats {
     frame_id: u64,
     // frame
     pub frame_duration: time::Duration,
     time: time::Duration,
     accumulator: time::Duration,
+    /// Current fixed update period
+    pub update_period: time::Duration,
+    /// Job timings of the frame
+    pub jobs: jobs::JobStats,
+    /// Number of snapshots in the history
+    pub snapshot_count: usize,
+    /// Memory used by the snapshot history, in bytes
+    pub snapshot_memory: usize,
+    /// Render thread stats, when the render pipeline is enabled
+    pub render_pipeline: render_thread::RenderStats,
     // loop
     /// Elapsed time between NewEvents and RedrawEventsCleared
     pub loop_duration: time::Duration,
@@ -55,15 +157,296 @@ pub struct GameStats {
     total_event_count: u64,
 }
 
+/// A fixed step counter, incremented on each fixed step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tick(pub u64);
+
+/// Loop services available to the game during updates.
+pub struct GameContext {
+    input: input::InputState,
+    text_input: text_input::TextInput,
+    cursor: cursor::Cursor,
+    timers: timers::TimerWheel,
+    scheduler: scheduler::Scheduler,
+    jobs: jobs::JobSystem,
+    executor: executor::Executor,
+    snapshots: snapshot::SnapshotHistory,
+    tick: Tick,
+    seed: u64,
+}
+
+impl GameContext {
+    pub(crate) fn new(
+        seed: u64,
+        scheduler: scheduler::Scheduler,
+        jobs: jobs::JobSystem,
+        executor: executor::Executor,
+        snapshots: snapshot::SnapshotHistory,
+    ) -> Self {
+        GameContext {
+            input: input::InputState::new(),
+            text_input: text_input::TextInput::new(),
+            cursor: cursor::Cursor::new(),
+            timers: timers::TimerWheel::new(),
+            scheduler,
+            jobs,
+            executor,
+            snapshots,
+            tick: Tick(0),
+            seed,
+        }
+    }
+
+    pub fn input(&self) -> &input::InputState {
+        &self.input
+    }
+
+    pub fn text_input(&mut self) -> &mut text_input::TextInput {
+        &mut self.text_input
+    }
+
+    pub fn cursor(&mut self) -> &mut cursor::Cursor {
+        &mut self.cursor
+    }
+
+    pub fn timers(&mut self) -> &mut timers::TimerWheel {
+        &mut self.timers
+    }
+
+    pub fn scheduler(&mut self) -> &mut scheduler::Scheduler {
+        &mut self.scheduler
+    }
+
+    /// Returns the job system, to spread work across cores.
+    pub fn jobs(&self) -> &jobs::JobSystem {
+        &self.jobs
+    }
+
+    /// Returns the async executor, to spawn tasks polled once per frame.
+    pub fn executor(&mut self) -> &mut executor::Executor {
+        &mut self.executor
+    }
+
+    /// Returns the snapshot history, to rewind or resimulate the game.
+    pub fn snapshots(&mut self) -> &mut snapshot::SnapshotHistory {
+        &mut self.snapshots
+    }
+
+    /// Returns the tick of the current (or last) fixed step.
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    fn handle_event<T>(&mut self, event: &Event<'_, T>) {
+        self.input.handle_event(event);
+        self.text_input.handle_event(event);
+        self.cursor.handle_event(event);
+    }
+
+    fn begin_step(&mut self) {
+        self.input.begin_step();
+        self.cursor.begin_step();
+    }
+
+    fn end_step(&mut self) {
+        self.input.end_step();
+        self.cursor.end_step();
+    }
+
+    /// Applies the services requests to the window.
+    fn apply(&mut self, window: &winit::window::Window) {
+        self.text_input.apply(window);
+        self.cursor.apply(window);
+    }
+
+    /// Returns the seed to use for random number generation.
+    /// It is saved in recordings so that replays are deterministic.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+/// Runs a game; `T` is the packet type of the render pipeline, if any
+/// (see `GameLoop::set_render_pipeline`).
 //#[derive(Default)]
-pub struct GameLoop {
+pub struct GameLoop<T = ()> {
     event_loop: winit::event_loop::EventLoop<()>,
+    setup: GameSetup,
+    seed: u64,
+    recorder: Option<replay::Recorder>,
+    player: Option<replay::Player>,
+    gamepad_backend: Option<Box<dyn gamepad::GamepadBackend>>,
+    clipboard: Option<Box<dyn clipboard::Clipboard>>,
+    window_state: Option<window_state::WindowStatePersister>,
+    scheduler: scheduler::Scheduler,
+    simulation: Option<Box<dyn simulation::SimulationControl>>,
+    render_pipeline: Option<Box<dyn render_thread::RenderPipeline<T>>>,
 }
 
-impl GameLoop {
+impl<T> GameLoop<T> {
     pub fn new() -> Self {
         let event_loop = winit::event_loop::EventLoop::new();
-        GameLoop { event_loop }
+        // game setup
+        let setup = GameSetup {
+            update_period: time::Duration::from_secs_f32(1.0 / 60.0),
+            redraw_on_resize: true,
+            resize_policy: resize::ResizePolicy::default(),
+            target_frame_rate: frame::TargetFrameRate::Unlimited,
+            adaptive_update_period: None,
+            extrapolation: false,
+            lockstep: false,
+            job_threads: 0,
+            async_budget: time::Duration::from_millis(2),
+            snapshots: None,
+            lag_time: None, //Some(time::Duration::from_millis(4)),
+        };
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        GameLoop {
+            event_loop,
+            setup,
+            seed,
+            recorder: None,
+            player: None,
+            gamepad_backend: None,
+            clipboard: None,
+            window_state: None,
+            scheduler: scheduler::Scheduler::new(),
+            simulation: None,
+            render_pipeline: None,
+        }
+    }
+
+    /// Sets how resize events are reported to `Game::resized`.
+    pub fn set_resize_policy(&mut self, resize_policy: resize::ResizePolicy) {
+        self.setup.resize_policy = resize_policy;
+    }
+
+    /// Sets the frame rate the loop is throttled to (unlimited by default).
+    pub fn set_target_frame_rate(&mut self, target_frame_rate: frame::TargetFrameRate) {
+        self.setup.target_frame_rate = target_frame_rate;
+    }
+
+    /// Lets the fixed update period adapt, within bounds, to the frame cost
+    /// and to the display refresh rate (see `Game::update_period_changed`).
+    /// The update period stays constant while recording or replaying.
+    pub fn set_adaptive_update_period(
+        &mut self,
+        min_period: time::Duration,
+        max_period: time::Duration,
+    ) {
+        assert!(min_period <= max_period, "invalid update period bounds");
+        self.setup.adaptive_update_period = Some((min_period, max_period));
+    }
+
+    /// Calls `Game::render_extrapolated` instead of `Game::render`, so that the game
+    /// can extrapolate the last fixed step (without the latency added by interpolation).
+    pub fn set_extrapolation(&mut self, extrapolation: bool) {
+        self.setup.extrapolation = extrapolation;
+    }
+
+    /// Runs each fixed step only once `Game::lockstep_ready` returns true, instead of
+    /// whenever an update period elapsed, so that the ticks (and the timers) follow a
+    /// lockstep session. The steps delayed while waiting are run later
+    /// (up to 8 update periods, the waiting time beyond is dropped).
+    pub fn set_lockstep(&mut self, lockstep: bool) {
+        self.setup.lockstep = lockstep;
+    }
+
+    /// Returns the scheduler, to register tasks run at given loop stages
+    /// (it is available to the game through `GameContext::scheduler` once running).
+    pub fn scheduler(&mut self) -> &mut scheduler::Scheduler {
+        &mut self.scheduler
+    }
+
+    /// Sets the number of job worker threads (0 by default: jobs run on the loop thread).
+    /// Jobs always run on the loop thread when recording or replaying.
+    pub fn set_job_threads(&mut self, thread_count: usize) {
+        self.setup.job_threads = thread_count;
+    }
+
+    /// Sets the time given to async tasks each frame (2 ms by default).
+    pub fn set_async_budget(&mut self, budget: time::Duration) {
+        self.setup.async_budget = budget;
+    }
+
+    /// Captures a snapshot of the game state (see `Game::snapshot`) every `interval` ticks,
+    /// keeping the last `capacity` ones, so that the game can be rewound.
+    /// Snapshots are disabled when recording or replaying.
+    pub fn set_snapshots(&mut self, interval: u64, capacity: usize) {
+        assert!(interval > 0, "snapshot interval must not be zero");
+        self.setup.snapshots = Some((interval, capacity));
+    }
+
+    /// Runs the fixed steps of `simulation` on a dedicated thread started with the loop:
+    /// live input events are forwarded to it, and the returned snapshots are read
+    /// from `Game::render` to interpolate (see `simulation::SimulationThread`).
+    /// The simulation is paced on its own, at the update period the loop starts with:
+    /// it is not recorded, replayed nor rewound with the loop's fixed steps.
+    pub fn set_simulation<S: simulation::Simulation>(
+        &mut self,
+        simulation: S,
+    ) -> io::Result<simulation::SimulationSnapshots<S::Snapshot>> {
+        let (control, snapshots) = simulation::spawn_stopped(simulation, self.setup.update_period)?;
+        self.simulation = Some(control);
+        Ok(snapshots)
+    }
+
+    /// Renders on a dedicated thread, up to `depth` frames behind the update:
+    /// `Game::render_packet` is called instead of `Game::render` and its packets are
+    /// rendered by `renderer` (see `render_thread::RenderThread`).
+    /// The render thread stats are reported in `GameStats::render_pipeline`.
+    pub fn set_render_pipeline<R: render_thread::Renderer<Packet = T>>(
+        &mut self,
+        renderer: R,
+        depth: usize,
+    ) -> io::Result<()> {
+        let render_thread = render_thread::RenderThread::spawn(renderer, depth)?;
+        self.render_pipeline = Some(Box::new(render_thread));
+        Ok(())
+    }
+
+    /// Sets the gamepad backend, polled once per frame before fixed updates.
+    pub fn set_gamepad_backend(&mut self, backend: Box<dyn gamepad::GamepadBackend>) {
+        self.gamepad_backend = Some(backend);
+    }
+
+    /// Enables gamepad support using gilrs.
+    #[cfg(feature = "gilrs")]
+    pub fn enable_gamepads(&mut self) -> Result<(), gilrs::Error> {
+        let backend = gamepad::GilrsBackend::new()?;
+        self.set_gamepad_backend(Box::new(backend));
+        Ok(())
+    }
+
+    /// Sets the clipboard used by the text input service (in memory by default).
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn clipboard::Clipboard>) {
+        self.clipboard = Some(clipboard);
+    }
+
+    /// Restores the window state saved by a previous run when building the window,
+    /// and saves it to a file on exit.
+    pub fn persist_window_state<P: AsRef<Path>>(&mut self, path: P) {
+        self.window_state = Some(window_state::WindowStatePersister::new(path));
+    }
+
+    /// Records input events, with the tick they are applied on, to a file.
+    pub fn record<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let recorder = replay::Recorder::create(path, self.setup.update_period, self.seed)?;
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Replays input events from a recording.
+    /// Live input is ignored and fixed steps run one per frame, without real-time pacing,
+    /// until the end of the recording.
+    pub fn replay<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let player = replay::Player::open(path)?;
+        self.setup.update_period = player.update_period();
+        self.seed = player.seed();
+        self.player = Some(player);
+        Ok(())
     }
     /*
         /// Returns the list of all the monitors available on the system.
@@ -73,15 +456,225 @@ impl GameLoop {
         self.event_loop.available_monitors()
     }
 
+    pub fn primary_monitor(&self) -> Option<MonitorHandle> {
+        self.event_loop.primary_monitor()
+    }
+
     pub fn build_window(
         &self,
         window_builder: winit::window::WindowBuilder,
     ) -> Result<winit::window::Window, winit::error::OsError> {
+        let window_builder = match &self.window_state {
+            Some(window_state) => {
+                let monitors: Vec<MonitorHandle> = self.available_monitors().collect();
+                let primary_monitor = self.primary_monitor();
+                window_state.restore(window_builder, &monitors, primary_monitor.as_ref())
+            }
+            None => window_builder,
+        };
         window_builder.build(&self.event_loop)
     }
 }
 
-impl Default for GameLoop {
+/// Runs a fixed step: fires the timers due, then updates the game.
+pub(crate) fn fixed_step<G: Game>(
+    game: &mut G,
+    context: &mut GameContext,
+    fired_timers: &mut Vec<timers::Fired>,
+    tick: u64,
+    time: time::Duration,
+    dt: time::Duration,
+) {
+    capture_snapshot(game, context, tick, time);
+    let tick = Tick(tick);
+    context.tick = tick;
+    context.begin_step();
+    context
+        .timers
+        .advance(tick, time::Instant::now(), fired_timers);
+    for timer in fired_timers.drain(..) {
+        match timer.callback {
+            Some(callback) => (callback.borrow_mut())(context, timer.id),
+            None => game.timer(context, timer.id),
+        }
+    }
+    game.update_fixed_step(context, tick, time, dt);
+    context.end_step();
+    context.executor.tick();
+}
+
+/// Runs the scheduled tasks due at a loop stage.
+pub(crate) fn run_tasks<G: Game>(
+    scheduler: &mut scheduler::Scheduler,
+    stage: Stage,
+    game: &G,
+    stats: &GameStats,
+) {
+    scheduler.run(&scheduler::TaskContext {
+        stage,
+        now: time::Instant::now(),
+        window: game.window(),
+        stats,
+    });
+}
+
+// captures the state at the beginning of the tick, when due
+fn capture_snapshot<G: Game>(
+    game: &mut G,
+    context: &mut GameContext,
+    tick: u64,
+    time: time::Duration,
+) {
+    if !context.snapshots.is_due(tick) {
+        return;
+    }
+    if let Some(snapshot) = game.snapshot() {
+        let cursor_delta = context.cursor.pending_delta();
+        context
+            .snapshots
+            .capture(tick, time, snapshot, &context.input, cursor_delta);
+    }
+}
+
+/// Rewinds or resimulates the game, as requested through `GameContext::snapshots`.
+fn apply_snapshot_request<G: Game>(
+    game: &mut G,
+    context: &mut GameContext,
+    state: &mut GameState,
+    dt: time::Duration,
+    window_id: winit::window::WindowId,
+) {
+    let request = match context.snapshots.take_request() {
+        Some(request) => request,
+        None => return,
+    };
+    let target = match request {
+        snapshot::Request::Rewind(tick) | snapshot::Request::Resimulate(tick) => tick,
+    };
+    let (tick, time, input, cursor_delta) = {
+        let (snapshot, restore_point) = match (
+            game.snapshot(),
+            context.snapshots.restore_point(target.min(state.tick)),
+        ) {
+            (Some(snapshot), Some(restore_point)) => (snapshot, restore_point),
+            _ => {
+                eprintln!("no snapshot to restore at tick {}", target);
+                return;
+            }
+        };
+        snapshot.restore(restore_point.data);
+        (
+            restore_point.tick,
+            restore_point.time,
+            restore_point.input.clone(),
+            restore_point.cursor_delta,
+        )
+    };
+    match request {
+        snapshot::Request::Rewind(_) => {
+            context.snapshots.truncate(tick);
+            context.timers.rebase(tick);
+            context.tick = Tick(tick);
+            state.tick = tick;
+            state.time = time;
+        }
+        snapshot::Request::Resimulate(_) => {
+            // same steps and input, without timers and async tasks
+            // (the live input is kept when rewinding, and restored once resimulated)
+            let live_input = mem::replace(&mut context.input, input);
+            let live_cursor = context.cursor.clone();
+            context.cursor.set_pending_delta(cursor_delta);
+            let live_text = context.text_input.replace_text(String::new());
+            let (mut tick, mut time) = (tick, time);
+            while tick < state.tick {
+                capture_snapshot(game, context, tick, time);
+                context.tick = Tick(tick);
+                context.begin_step();
+                game.update_fixed_step(context, Tick(tick), time, dt);
+                context.end_step();
+                tick += 1;
+                time += dt;
+                // the events of the current tick were delivered live
+                if tick < state.tick {
+                    for input_event in context.snapshots.events(tick) {
+                        resimulate_event(context, &input_event, window_id);
+                    }
+                }
+            }
+            context.input = live_input;
+            context.cursor = live_cursor;
+            context.text_input.replace_text(live_text);
+        }
+    }
+}
+
+fn replay_event<G: Game>(
+    game: &mut G,
+    context: &mut GameContext,
+    input_event: &replay::InputEvent,
+    window_id: winit::window::WindowId,
+) {
+    if let replay::InputEvent::Gamepad(gamepad_event) = input_event {
+        context.input.handle_gamepad_event(gamepad_event);
+        game.gamepad(gamepad_event);
+    } else if let Some(event) = input_event.to_event::<()>(window_id) {
+        context.handle_event(&event);
+        game.event(&event);
+    }
+}
+
+// the game got the event live, only the input state and the cursor motion are replayed
+fn resimulate_event(
+    context: &mut GameContext,
+    input_event: &replay::InputEvent,
+    window_id: winit::window::WindowId,
+) {
+    if let replay::InputEvent::Gamepad(gamepad_event) = input_event {
+        context.input.handle_gamepad_event(gamepad_event);
+    } else if let Some(event) = input_event.to_event::<()>(window_id) {
+        context.input.handle_event(&event);
+        context.cursor.handle_event(&event);
+    }
+}
+
+/// Records an input event, stops recording on error.
+fn record(recorder: &mut Option<replay::Recorder>, tick: u64, input_event: &replay::InputEvent) {
+    if let Some(r) = recorder {
+        if let Err(e) = r.record(tick, input_event) {
+            eprintln!("failed to record input ({}), recording stopped", e);
+            *recorder = None;
+        }
+    }
+}
+
+/// Throttles the frame rate and adapts the update period
+/// according to the monitor the window is on.
+fn set_monitor_refresh_rate(
+    frame_rate_throttle: &mut frame::FrameRateThrottle,
+    adaptive_step: &mut Option<step::AdaptiveStep>,
+    window: Option<&winit::window::Window>,
+) {
+    let refresh_rate = window
+        .and_then(|w| w.current_monitor())
+        .and_then(|m| m.refresh_rate_millihertz());
+    frame_rate_throttle.set_monitor_refresh_rate(refresh_rate);
+    if let Some(adaptive_step) = adaptive_step {
+        adaptive_step.set_display_refresh_rate(refresh_rate);
+    }
+}
+
+/// Makes sure the event loop wakes up no later than `instant`.
+fn wake_up_at(control_flow: &mut ControlFlow, instant: time::Instant) {
+    match *control_flow {
+        ControlFlow::Wait => *control_flow = ControlFlow::WaitUntil(instant),
+        ControlFlow::WaitUntil(other) if instant < other => {
+            *control_flow = ControlFlow::WaitUntil(instant)
+        }
+        _ => {}
+    }
+}
+
+impl<T> Default for GameLoop<T> {
     fn default() -> Self {
         Self::new()
     }
@@ -107,8 +700,8 @@ impl Default for GameLoop {
 // - sub-stepping
 //
 // MAILBOX mode
-impl GameLoop {
-    pub fn run<G: 'static + Game>(self, mut game: G) {
+impl<T: Send + 'static> GameLoop<T> {
+    pub fn run<G: 'static + Game<RenderPacket = T>>(self, mut game: G) {
         // FIXME do only when needed (i.e. WaitUntil is used, not minimized, ...)
         //let period = timer::set_timer_max_resolution();
         //println!("timer resolution set to {:?}", period);
@@ -116,13 +709,26 @@ impl GameLoop {
         let mut frame_count = frame::FrameCount::new();
         let mut frame_rate_throttle = frame::FrameRateThrottle::new();
         //frame_rate_throttle.set_target_frame_rate(frame::TargetFrameRate::FramePerSeconds(60));
-        frame_rate_throttle.set_target_frame_rate(frame::TargetFrameRate::Unlimited);
+        frame_rate_throttle.set_target_frame_rate(self.setup.target_frame_rate);
 
-        // game setup
-        let setup = GameSetup {
-            update_period: time::Duration::from_secs_f32(1.0 / 60.0),
-            redraw_on_resize: true,
-            lag_time: None, //Some(time::Duration::from_millis(4)),
+        let mut setup = self.setup;
+        let mut recorder = self.recorder;
+        let mut player = self.player;
+        let mut gamepad_backend = self.gamepad_backend;
+        let mut gamepad_events = Vec::new();
+        let mut resize_coalescer = resize::ResizeCoalescer::new(setup.resize_policy);
+        let mut window_state = self.window_state;
+        let mut simulation = self.simulation;
+        let mut render_pipeline = self.render_pipeline;
+        // replays need a constant update period
+        let mut adaptive_step = match setup.adaptive_update_period {
+            Some((min_period, max_period)) if recorder.is_none() && player.is_none() => {
+                let adaptive_step =
+                    step::AdaptiveStep::new(setup.update_period, min_period, max_period);
+                setup.update_period = adaptive_step.period();
+                Some(adaptive_step)
+            }
+            _ => None,
         };
 
         // game state
@@ -132,10 +738,39 @@ impl GameLoop {
             last_frame_time: None,
             time: time::Duration::new(0, 0),
             accumulator: time::Duration::new(0, 0),
+            tick: 0,
             // loop
             loop_start_time: time::Instant::now(),
         };
 
+        // game context
+        // jobs run on the loop thread when recording or replaying (deterministic replays)
+        let job_threads = if recorder.is_none() && player.is_none() {
+            setup.job_threads
+        } else {
+            0
+        };
+        // replays can't be rewound
+        let snapshots = match setup.snapshots {
+            Some((interval, capacity)) if recorder.is_none() && player.is_none() => {
+                snapshot::SnapshotHistory::new(interval, capacity)
+            }
+            _ => snapshot::SnapshotHistory::disabled(),
+        };
+        let mut context = GameContext::new(
+            self.seed,
+            self.scheduler,
+            jobs::JobSystem::new(job_threads),
+            executor::Executor::new(Some(self.event_loop.create_proxy())),
+            snapshots,
+        );
+        let mut fired_timers = Vec::new();
+        if let Some(clipboard) = self.clipboard {
+            context.text_input.set_clipboard(clipboard);
+        }
+        // window receiving replayed events
+        let mut window_id = unsafe { winit::window::WindowId::dummy() };
+
         // game stats
         let mut stats = GameStats {
             frame_id: 0,
@@ -143,6 +778,11 @@ impl GameLoop {
             frame_duration: time::Duration::new(0, 0),
             time: time::Duration::new(0, 0),
             accumulator: time::Duration::new(0, 0),
+            update_period: setup.update_period,
+            jobs: jobs::JobStats::default(),
+            snapshot_count: 0,
+            snapshot_memory: 0,
+            render_pipeline: render_thread::RenderStats::default(),
             // loop time
             loop_duration: time::Duration::new(0, 0),
             event: time::Duration::new(0, 0),
@@ -158,6 +798,7 @@ impl GameLoop {
         let mut invalidated = false;
         let mut resized = false;
         let mut minimized = false;
+        let mut suspended = false;
 
         self.event_loop.run(move |event, _, control_flow| {
             {
@@ -176,7 +817,23 @@ impl GameLoop {
             match event {
                 Event::DeviceEvent { .. } | Event::WindowEvent { .. } => {
                     let start_time = time::Instant::now();
-                    game.event(&event);
+                    if let Event::WindowEvent { window_id: id, .. } = event {
+                        window_id = id;
+                    }
+                    let input_event = replay::InputEvent::from_event(&event);
+                    // live input is ignored while replaying
+                    if player.is_none() || input_event.is_none() {
+                        if let Some(input_event) = &input_event {
+                            // events are applied on the next fixed step
+                            record(&mut recorder, state.tick, input_event);
+                            context.snapshots.record(state.tick, input_event);
+                            if let Some(simulation) = &simulation {
+                                simulation.forward(*input_event);
+                            }
+                        }
+                        context.handle_event(&event);
+                        game.event(&event);
+                    }
                     stats.event += time::Instant::now() - start_time;
                 }
                 _ => (),
@@ -197,25 +854,58 @@ impl GameLoop {
                             *control_flow = ControlFlow::Exit
                         }
                     }
+                    WindowEvent::Moved(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                        // the window may have moved to another monitor
+                        set_monitor_refresh_rate(
+                            &mut frame_rate_throttle,
+                            &mut adaptive_step,
+                            game.window(),
+                        );
+                        if let (Some(window_state), Some(window)) =
+                            (&mut window_state, game.window())
+                        {
+                            window_state.track(window);
+                        }
+                    }
                     WindowEvent::Resized(new_size) => {
                         minimized = new_size.width == 0 && new_size.height == 0;
-                        // hack to ignore initial (and spurious) resize events
+                        if let (Some(window_state), Some(window)) =
+                            (&mut window_state, game.window())
+                        {
+                            if !minimized {
+                                window_state.track(window);
+                            }
+                        }
                         if init {
-                            println!("Ignored : {:?}", event);
-                        } else {
-                            println!("Resized : {:?}", event);
-                            /*use backtrace::Backtrace;
-                            let bt = Backtrace::new();
-                            println!("{:?}", bt);*/
+                            // resize events received on init give the initial size
+                            resize_coalescer.set_size(new_size);
+                        } else if let Some(size) =
+                            resize_coalescer.resized(new_size, time::Instant::now())
+                        {
                             resized = true;
-                            game.resized();
+                            game.resized(size);
                         }
                     }
                     _ => {}
                 },
                 Event::UserEvent { .. } => {}
-                Event::Suspended => {}
-                Event::Resumed => {}
+                Event::Suspended => {
+                    suspended = true;
+                    game.suspended();
+                    // no updates nor redraws until resumed
+                    *control_flow = ControlFlow::Wait;
+                }
+                Event::Resumed => {
+                    if suspended {
+                        suspended = false;
+                        // don't let simulation time jump forward
+                        state.last_frame_time = Some(time::Instant::now());
+                        state.accumulator = cmp::min(state.accumulator, setup.update_period);
+                        // make sure the next iteration is not a cancelled wait
+                        *control_flow = ControlFlow::Poll;
+                    }
+                    game.resumed();
+                }
                 Event::NewEvents(start_cause) => {
                     //println!("NewEvents : {:?}", start_cause);
                     // initialize state
@@ -230,8 +920,15 @@ impl GameLoop {
                         StartCause::Init => {
                             //last_time = time::Instant::now();
                             game.init();
-                            // hack to ignore initial (and spurious) resize events
                             init = true;
+                            if let Some(simulation) = &simulation {
+                                simulation.start(setup.update_period);
+                            }
+                            set_monitor_refresh_rate(
+                                &mut frame_rate_throttle,
+                                &mut adaptive_step,
+                                game.window(),
+                            );
                             state.time = time::Duration::new(0, 0);
                             state.accumulator = time::Duration::new(0, 0);
                         }
@@ -260,6 +957,7 @@ impl GameLoop {
                         }
                     }
                     game.start();
+                    run_tasks(&mut context.scheduler, Stage::NewEvents, &game, &stats);
                 }
                 Event::MainEventsCleared => {
                     // Application update code.
@@ -267,11 +965,88 @@ impl GameLoop {
                     let now = time::Instant::now();
                     let start_time = now;
                     state.frame_count += 1;
-                    if invalidated {
+                    // notify coalesced resize events (kept until resumed)
+                    if !suspended {
+                        if let Some(size) = resize_coalescer.poll(now) {
+                            resized = true;
+                            game.resized(size);
+                        }
+                    }
+                    // poll gamepads (live input is ignored while replaying)
+                    if let (false, Some(backend)) = (suspended, &mut gamepad_backend) {
+                        backend.poll(&mut gamepad_events);
+                        for gamepad_event in gamepad_events.drain(..) {
+                            if player.is_none() {
+                                let input_event = replay::InputEvent::Gamepad(gamepad_event);
+                                record(&mut recorder, state.tick, &input_event);
+                                context.snapshots.record(state.tick, &input_event);
+                                if let Some(simulation) = &simulation {
+                                    simulation.forward(input_event);
+                                }
+                                context.input.handle_gamepad_event(&gamepad_event);
+                                game.gamepad(&gamepad_event);
+                            }
+                        }
+                    }
+                    if invalidated && !suspended {
+                        run_tasks(
+                            &mut context.scheduler,
+                            Stage::BeforeFixedUpdate,
+                            &game,
+                            &stats,
+                        );
+                    }
+                    if invalidated && !suspended {
+                        // rewind or resimulate as requested by the last frame
+                        apply_snapshot_request(
+                            &mut game,
+                            &mut context,
+                            &mut state,
+                            setup.update_period,
+                            window_id,
+                        );
+                    }
+                    if let (true, false, Some(p)) = (invalidated, suspended, &mut player) {
+                        // replay: one fixed step per frame
+                        for input_event in p.events(state.tick) {
+                            replay_event(&mut game, &mut context, &input_event, window_id);
+                        }
+                        fixed_step(
+                            &mut game,
+                            &mut context,
+                            &mut fired_timers,
+                            state.tick,
+                            state.time,
+                            setup.update_period,
+                        );
+                        state.time += setup.update_period;
+                        state.tick += 1;
+                        game.update(&mut context, state.time);
+                        if p.is_finished(state.tick) {
+                            game.replay_finished(Tick(state.tick));
+                            player = None;
+                            state.accumulator = time::Duration::new(0, 0);
+                        }
+                    } else if invalidated && !suspended {
                         match state.last_frame_time {
+                            None if setup.lockstep
+                                && !game.lockstep_ready(&mut context, Tick(state.tick)) =>
+                            {
+                                game.update(&mut context, state.time);
+                            }
                             None => {
-                                game.update_fixed_step(state.time, setup.update_period);
-                                game.update(state.time);
+                                fixed_step(
+                                    &mut game,
+                                    &mut context,
+                                    &mut fired_timers,
+                                    state.tick,
+                                    state.time,
+                                    setup.update_period,
+                                );
+                                // keep time == tick * update_period (deterministic replays)
+                                state.tick += 1;
+                                state.time += setup.update_period;
+                                game.update(&mut context, state.time);
                             }
                             Some(_last_time) => {
                                 //let frame_duration = time::Duration::from_secs_f32(1.0 / 60.0); //now - last_time;
@@ -281,9 +1056,27 @@ impl GameLoop {
                                 // TODO cap the number of iterations to avoid spiral of death...
                                 //let mut update_count = 0;
                                 while state.accumulator >= setup.update_period {
+                                    if setup.lockstep
+                                        && !game.lockstep_ready(&mut context, Tick(state.tick))
+                                    {
+                                        // waiting for the inputs of the other players
+                                        state.accumulator = cmp::min(
+                                            state.accumulator,
+                                            setup.update_period * MAX_LOCKSTEP_LAG,
+                                        );
+                                        break;
+                                    }
                                     // this is pointless unless we have a physics engine that prefers fixed time step (say 10ms)
                                     // currently we don't have a physics engine (and why is update_period equals to 1/60 s?)
-                                    game.update_fixed_step(state.time, setup.update_period);
+                                    fixed_step(
+                                        &mut game,
+                                        &mut context,
+                                        &mut fired_timers,
+                                        state.tick,
+                                        state.time,
+                                        setup.update_period,
+                                    );
+                                    state.tick += 1;
                                     state.time += setup.update_period;
                                     state.accumulator -= setup.update_period;
                                     //update_count += 1;
@@ -312,23 +1105,50 @@ impl GameLoop {
                                         state.accumulator
                                     )
                                 }*/
-                                game.update(state.time + state.accumulator);
+                                game.update(&mut context, state.time + state.accumulator);
                             }
                         };
                     }
 
+                    if let Some(window) = game.window() {
+                        context.apply(window);
+                    }
+                    if !suspended {
+                        // also polled when woken up by a task
+                        context.executor.poll(invalidated, setup.async_budget);
+                    }
+                    if invalidated && !suspended {
+                        run_tasks(&mut context.scheduler, Stage::AfterUpdate, &game, &stats);
+                        context.text_input.end_frame();
+                    }
+
                     // update stats
                     stats.frame_id = state.frame_count;
                     stats.frame_duration = now - state.last_frame_time.unwrap_or(now);
                     stats.time = state.time;
                     stats.accumulator = state.accumulator;
+                    stats.update_period = setup.update_period;
+                    stats.jobs = context.jobs.take_stats();
+                    stats.snapshot_count = context.snapshots.len();
+                    stats.snapshot_memory = context.snapshots.memory_usage();
+                    if let Some(render_pipeline) = &render_pipeline {
+                        stats.render_pipeline = render_pipeline.stats();
+                    }
+
+                    // the recording survives a crash
+                    if let Some(r) = &mut recorder {
+                        if let Err(e) = r.flush() {
+                            eprintln!("failed to save recording ({}), recording stopped", e);
+                            recorder = None;
+                        }
+                    }
 
                     // update state
                     state.last_frame_time = Some(now);
 
                     //println!("{} {} {}", redraw, resized, minimized);
                     let redraw = invalidated || (setup.redraw_on_resize && resized);
-                    if redraw && !minimized {
+                    if redraw && !minimized && !suspended {
                         // Queue a RedrawRequested event.
                         //println!("REDRAW REQUESTED");
                         game.request_redraw();
@@ -345,9 +1165,27 @@ impl GameLoop {
                     //println!("RedrawRequested");
                     let start_time = time::Instant::now();
                     let redraw = invalidated || (setup.redraw_on_resize && resized);
-                    if redraw && !minimized {
+                    if redraw && !minimized && !suspended {
                         //println!("REDRAW");
-                        game.render();
+                        if let Some(render_pipeline) = &render_pipeline {
+                            // blocks only when the pipeline is full
+                            if let Some(packet) = game.render_packet() {
+                                render_pipeline.submit(packet);
+                            }
+                        } else if setup.extrapolation {
+                            // replayed steps are not paced in real time
+                            let elapsed = match (&player, state.last_frame_time) {
+                                (None, Some(frame_time)) => {
+                                    state.accumulator
+                                        + start_time.saturating_duration_since(frame_time)
+                                }
+                                _ => state.accumulator,
+                            };
+                            game.render_extrapolated(elapsed);
+                        } else {
+                            game.render();
+                        }
+                        run_tasks(&mut context.scheduler, Stage::AfterRender, &game, &stats);
                     }
                     stats.render = time::Instant::now() - start_time;
                 }
@@ -360,20 +1198,62 @@ impl GameLoop {
                     // while "probes" will be seen in the current frame
                     // !!!
                     game.stats(&stats);
+                    run_tasks(
+                        &mut context.scheduler,
+                        Stage::RedrawEventsCleared,
+                        &game,
+                        &stats,
+                    );
 
-                    if invalidated && !minimized {
+                    if invalidated && !minimized && !suspended {
                         frame_count.frame();
                         frame_rate_throttle.frame();
                         match frame_rate_throttle.wait_until() {
-                            Some(instant) => *control_flow = ControlFlow::WaitUntil(instant),
-                            None => *control_flow = ControlFlow::Poll,
+                            Some(instant) if player.is_none() => {
+                                *control_flow = ControlFlow::WaitUntil(instant)
+                            }
+                            _ => *control_flow = ControlFlow::Poll,
+                        }
+                        // adapt the update period to the frame cost
+                        if let Some(adaptive_step) = &mut adaptive_step {
+                            if let Some(update_period) =
+                                adaptive_step.frame(stats.update + stats.render)
+                            {
+                                setup.update_period = update_period;
+                                game.update_period_changed(update_period);
+                            }
                         }
                     }
+                    // wake up when a debounced resize is due
+                    if let Some(instant) = resize_coalescer.wait_until() {
+                        wake_up_at(control_flow, instant);
+                    }
+                    // poll the async tasks left over by the time budget
+                    if !suspended && context.executor.has_ready() {
+                        wake_up_at(control_flow, time::Instant::now());
+                    }
+                    // wake up when a scheduled task is due
+                    if let Some(instant) = context.scheduler.wait_until(time::Instant::now()) {
+                        wake_up_at(control_flow, instant);
+                    }
                     //*control_flow = ControlFlow::Poll;
                     //println!("***** {:?}", control_flow);
                     //println!("***** {:?}", frame_rate_throttle.wait_until());
                 }
                 Event::LoopDestroyed => {
+                    if let Some(window_state) = &mut window_state {
+                        window_state.save(game.window());
+                    }
+                    if let Some(r) = &mut recorder {
+                        if let Err(e) = r.finish(state.tick) {
+                            eprintln!("failed to save recording ({})", e);
+                        }
+                    }
+                    if let Some(simulation) = &mut simulation {
+                        simulation.stop();
+                    }
+                    // renders the packets in flight before the game is destroyed
+                    render_pipeline = None;
                     game.destroy();
                 }
             }
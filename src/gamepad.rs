@@ -0,0 +1,305 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Identifies a gamepad (stable while the gamepad stays connected).
+pub type GamepadId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    // action pad
+    South,
+    East,
+    North,
+    West,
+    C,
+    Z,
+    // triggers
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    // menu pad
+    Select,
+    Start,
+    Mode,
+    // sticks
+    LeftThumb,
+    RightThumb,
+    // d-pad
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+    DPadX,
+    DPadY,
+}
+
+pub(crate) const BUTTONS: [GamepadButton; 19] = [
+    GamepadButton::South,
+    GamepadButton::East,
+    GamepadButton::North,
+    GamepadButton::West,
+    GamepadButton::C,
+    GamepadButton::Z,
+    GamepadButton::LeftTrigger,
+    GamepadButton::LeftTrigger2,
+    GamepadButton::RightTrigger,
+    GamepadButton::RightTrigger2,
+    GamepadButton::Select,
+    GamepadButton::Start,
+    GamepadButton::Mode,
+    GamepadButton::LeftThumb,
+    GamepadButton::RightThumb,
+    GamepadButton::DPadUp,
+    GamepadButton::DPadDown,
+    GamepadButton::DPadLeft,
+    GamepadButton::DPadRight,
+];
+
+pub(crate) const AXES: [GamepadAxis; 8] = [
+    GamepadAxis::LeftStickX,
+    GamepadAxis::LeftStickY,
+    GamepadAxis::LeftZ,
+    GamepadAxis::RightStickX,
+    GamepadAxis::RightStickY,
+    GamepadAxis::RightZ,
+    GamepadAxis::DPadX,
+    GamepadAxis::DPadY,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Connected(GamepadId),
+    Disconnected(GamepadId),
+    ButtonPressed(GamepadId, GamepadButton),
+    ButtonReleased(GamepadId, GamepadButton),
+    /// Axis value in [-1.0, 1.0].
+    AxisChanged(GamepadId, GamepadAxis, f32),
+}
+
+// GamepadBackend
+
+/// Source of gamepad events, polled once per frame by the game loop.
+pub trait GamepadBackend {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>);
+}
+
+// FakeGamepadBackend
+
+/// A backend fed by hand, to exercise gamepad handling without hardware.
+///
+/// Clones share the same event queue: keep one clone to inject events
+/// and give the other one to `GameLoop::set_gamepad_backend`.
+#[derive(Clone, Default)]
+pub struct FakeGamepadBackend {
+    events: Rc<RefCell<VecDeque<GamepadEvent>>>,
+}
+
+impl FakeGamepadBackend {
+    pub fn new() -> FakeGamepadBackend {
+        Self::default()
+    }
+
+    /// Queues an event, it will be delivered on the next poll.
+    pub fn push(&self, event: GamepadEvent) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    pub fn connect(&self, id: GamepadId) {
+        self.push(GamepadEvent::Connected(id));
+    }
+
+    pub fn disconnect(&self, id: GamepadId) {
+        self.push(GamepadEvent::Disconnected(id));
+    }
+
+    pub fn press(&self, id: GamepadId, button: GamepadButton) {
+        self.push(GamepadEvent::ButtonPressed(id, button));
+    }
+
+    pub fn release(&self, id: GamepadId, button: GamepadButton) {
+        self.push(GamepadEvent::ButtonReleased(id, button));
+    }
+
+    pub fn move_axis(&self, id: GamepadId, axis: GamepadAxis, value: f32) {
+        self.push(GamepadEvent::AxisChanged(id, axis, value));
+    }
+}
+
+impl GamepadBackend for FakeGamepadBackend {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>) {
+        events.extend(self.events.borrow_mut().drain(..));
+    }
+}
+
+// GilrsBackend
+
+#[cfg(feature = "gilrs")]
+pub struct GilrsBackend {
+    gilrs: gilrs::Gilrs,
+    // gamepads connected before the first poll
+    connected: Vec<GamepadEvent>,
+}
+
+#[cfg(feature = "gilrs")]
+impl GilrsBackend {
+    pub fn new() -> Result<GilrsBackend, gilrs::Error> {
+        let gilrs = gilrs::Gilrs::new()?;
+        let connected = gilrs
+            .gamepads()
+            .map(|(id, _)| GamepadEvent::Connected(id.into()))
+            .collect();
+        Ok(GilrsBackend { gilrs, connected })
+    }
+
+    fn button(button: gilrs::Button) -> Option<GamepadButton> {
+        use gilrs::Button::*;
+        Some(match button {
+            South => GamepadButton::South,
+            East => GamepadButton::East,
+            North => GamepadButton::North,
+            West => GamepadButton::West,
+            C => GamepadButton::C,
+            Z => GamepadButton::Z,
+            LeftTrigger => GamepadButton::LeftTrigger,
+            LeftTrigger2 => GamepadButton::LeftTrigger2,
+            RightTrigger => GamepadButton::RightTrigger,
+            RightTrigger2 => GamepadButton::RightTrigger2,
+            Select => GamepadButton::Select,
+            Start => GamepadButton::Start,
+            Mode => GamepadButton::Mode,
+            LeftThumb => GamepadButton::LeftThumb,
+            RightThumb => GamepadButton::RightThumb,
+            DPadUp => GamepadButton::DPadUp,
+            DPadDown => GamepadButton::DPadDown,
+            DPadLeft => GamepadButton::DPadLeft,
+            DPadRight => GamepadButton::DPadRight,
+            Unknown => return None,
+        })
+    }
+
+    fn axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+        use gilrs::Axis::*;
+        Some(match axis {
+            LeftStickX => GamepadAxis::LeftStickX,
+            LeftStickY => GamepadAxis::LeftStickY,
+            LeftZ => GamepadAxis::LeftZ,
+            RightStickX => GamepadAxis::RightStickX,
+            RightStickY => GamepadAxis::RightStickY,
+            RightZ => GamepadAxis::RightZ,
+            DPadX => GamepadAxis::DPadX,
+            DPadY => GamepadAxis::DPadY,
+            Unknown => return None,
+        })
+    }
+}
+
+#[cfg(feature = "gilrs")]
+impl GamepadBackend for GilrsBackend {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>) {
+        use gilrs::EventType;
+        events.append(&mut self.connected);
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let id = id.into();
+            let event = match event {
+                EventType::Connected => Some(GamepadEvent::Connected(id)),
+                EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+                EventType::ButtonPressed(button, _) => {
+                    Self::button(button).map(|button| GamepadEvent::ButtonPressed(id, button))
+                }
+                EventType::ButtonReleased(button, _) => {
+                    Self::button(button).map(|button| GamepadEvent::ButtonReleased(id, button))
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    Self::axis(axis).map(|axis| GamepadEvent::AxisChanged(id, axis, value))
+                }
+                _ => None,
+            };
+            events.extend(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputState;
+
+    // delivers the queued fake events to the input state, as the game loop does each frame
+    fn poll(backend: &mut FakeGamepadBackend, input: &mut InputState) {
+        let mut events = Vec::new();
+        backend.poll(&mut events);
+        for event in &events {
+            input.handle_gamepad_event(event);
+        }
+    }
+
+    #[test]
+    fn fake_backend_drives_input_state() {
+        let mut backend = FakeGamepadBackend::new();
+        let injector = backend.clone();
+        let mut input = InputState::new();
+
+        injector.connect(1);
+        injector.press(1, GamepadButton::South);
+        injector.move_axis(1, GamepadAxis::LeftStickX, -0.5);
+        poll(&mut backend, &mut input);
+        input.begin_step();
+        assert_eq!(input.gamepads().collect::<Vec<_>>(), vec![1]);
+        assert!(input.is_gamepad_button_down(1, GamepadButton::South));
+        assert!(input.is_gamepad_button_pressed(1, GamepadButton::South));
+        assert!(!input.is_gamepad_button_down(1, GamepadButton::East));
+        assert_eq!(input.gamepad_axis(1, GamepadAxis::LeftStickX), -0.5);
+        assert_eq!(input.gamepad_axis(1, GamepadAxis::LeftStickY), 0.0);
+        input.end_step();
+
+        // edges last one step, down state and axes persist
+        poll(&mut backend, &mut input);
+        input.begin_step();
+        assert!(input.is_gamepad_button_down(1, GamepadButton::South));
+        assert!(!input.is_gamepad_button_pressed(1, GamepadButton::South));
+        assert_eq!(input.gamepad_axis(1, GamepadAxis::LeftStickX), -0.5);
+        input.end_step();
+
+        injector.release(1, GamepadButton::South);
+        poll(&mut backend, &mut input);
+        input.begin_step();
+        assert!(!input.is_gamepad_button_down(1, GamepadButton::South));
+        assert!(input.is_gamepad_button_released(1, GamepadButton::South));
+        input.end_step();
+    }
+
+    #[test]
+    fn disconnect_releases_buttons() {
+        let mut backend = FakeGamepadBackend::new();
+        let injector = backend.clone();
+        let mut input = InputState::new();
+
+        injector.connect(0);
+        injector.press(0, GamepadButton::Start);
+        injector.move_axis(0, GamepadAxis::RightZ, 1.0);
+        poll(&mut backend, &mut input);
+        input.begin_step();
+        input.end_step();
+
+        injector.disconnect(0);
+        poll(&mut backend, &mut input);
+        input.begin_step();
+        assert_eq!(input.gamepads().count(), 0);
+        assert!(!input.is_gamepad_button_down(0, GamepadButton::Start));
+        assert!(input.is_gamepad_button_released(0, GamepadButton::Start));
+        assert_eq!(input.gamepad_axis(0, GamepadAxis::RightZ), 0.0);
+        input.end_step();
+    }
+}
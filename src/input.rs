@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
+
+use crate::gamepad::{GamepadAxis, GamepadButton, GamepadEvent, GamepadId};
+
+// InputEdges
+
+/// Transitions and deltas accumulated between two fixed update steps.
+#[derive(Clone, Default)]
+struct InputEdges {
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    gamepad_buttons_pressed: HashSet<(GamepadId, GamepadButton)>,
+    gamepad_buttons_released: HashSet<(GamepadId, GamepadButton)>,
+    mouse_delta: (f64, f64),
+    scroll_lines: (f32, f32),
+    scroll_pixels: (f64, f64),
+}
+
+// GamepadState
+
+#[derive(Clone, Default)]
+struct GamepadState {
+    buttons_down: HashSet<GamepadButton>,
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+// InputState
+
+/// Keyboard, mouse and gamepad state maintained by the game loop.
+///
+/// Down state (keys, buttons, modifiers, cursor position, axes) is always current.
+/// Edges (pressed/released) and deltas (mouse motion, scroll) are accumulated
+/// between fixed update steps and are visible during the next `Game::update_fixed_step`,
+/// so that presses occurring between two steps are not lost.
+#[derive(Clone, Default)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    modifiers: ModifiersState,
+    cursor_position: Option<PhysicalPosition<f64>>,
+    gamepads: HashMap<GamepadId, GamepadState>,
+    // edges accumulated since the last step
+    pending: InputEdges,
+    // edges of the current step
+    current: InputEdges,
+}
+
+impl InputState {
+    pub fn new() -> InputState {
+        Self::default()
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.current.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: VirtualKeyCode) -> bool {
+        self.current.keys_released.contains(&key)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = &VirtualKeyCode> {
+        self.keys_down.iter()
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.current.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_button_released(&self, button: MouseButton) -> bool {
+        self.current.buttons_released.contains(&button)
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Returns the cursor position, or `None` if the cursor is outside of the window.
+    pub fn cursor_position(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor_position
+    }
+
+    /// Returns the raw mouse motion of the current step.
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.current.mouse_delta
+    }
+
+    /// Returns the scroll amount of the current step, in lines.
+    pub fn scroll_lines(&self) -> (f32, f32) {
+        self.current.scroll_lines
+    }
+
+    /// Returns the scroll amount of the current step, in pixels.
+    pub fn scroll_pixels(&self) -> (f64, f64) {
+        self.current.scroll_pixels
+    }
+
+    /// Returns the connected gamepads.
+    pub fn gamepads(&self) -> impl Iterator<Item = GamepadId> + '_ {
+        self.gamepads.keys().copied()
+    }
+
+    pub fn is_gamepad_button_down(&self, id: GamepadId, button: GamepadButton) -> bool {
+        self.gamepads
+            .get(&id)
+            .is_some_and(|gamepad| gamepad.buttons_down.contains(&button))
+    }
+
+    pub fn is_gamepad_button_pressed(&self, id: GamepadId, button: GamepadButton) -> bool {
+        self.current.gamepad_buttons_pressed.contains(&(id, button))
+    }
+
+    pub fn is_gamepad_button_released(&self, id: GamepadId, button: GamepadButton) -> bool {
+        self.current
+            .gamepad_buttons_released
+            .contains(&(id, button))
+    }
+
+    /// Returns the axis value, in [-1.0, 1.0].
+    pub fn gamepad_axis(&self, id: GamepadId, axis: GamepadAxis) -> f32 {
+        self.gamepads
+            .get(&id)
+            .and_then(|gamepad| gamepad.axes.get(&axis))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub(crate) fn handle_gamepad_event(&mut self, event: &GamepadEvent) {
+        match *event {
+            GamepadEvent::Connected(id) => {
+                self.gamepads.entry(id).or_default();
+            }
+            GamepadEvent::Disconnected(id) => {
+                if let Some(gamepad) = self.gamepads.remove(&id) {
+                    for button in gamepad.buttons_down {
+                        self.pending.gamepad_buttons_released.insert((id, button));
+                    }
+                }
+            }
+            GamepadEvent::ButtonPressed(id, button) => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                if gamepad.buttons_down.insert(button) {
+                    self.pending.gamepad_buttons_pressed.insert((id, button));
+                }
+            }
+            GamepadEvent::ButtonReleased(id, button) => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                if gamepad.buttons_down.remove(&button) {
+                    self.pending.gamepad_buttons_released.insert((id, button));
+                }
+            }
+            GamepadEvent::AxisChanged(id, axis, value) => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                gamepad.axes.insert(axis, value);
+            }
+        }
+    }
+
+    pub(crate) fn handle_event<T>(&mut self, event: &Event<'_, T>) {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state,
+                            ..
+                        },
+                    ..
+                } => match state {
+                    ElementState::Pressed => {
+                        // ignore key repeat
+                        if self.keys_down.insert(*key) {
+                            self.pending.keys_pressed.insert(*key);
+                        }
+                    }
+                    ElementState::Released => {
+                        if self.keys_down.remove(key) {
+                            self.pending.keys_released.insert(*key);
+                        }
+                    }
+                },
+                WindowEvent::MouseInput { state, button, .. } => match state {
+                    ElementState::Pressed => {
+                        if self.buttons_down.insert(*button) {
+                            self.pending.buttons_pressed.insert(*button);
+                        }
+                    }
+                    ElementState::Released => {
+                        if self.buttons_down.remove(button) {
+                            self.pending.buttons_released.insert(*button);
+                        }
+                    }
+                },
+                WindowEvent::MouseWheel { delta, .. } => match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        self.pending.scroll_lines.0 += x;
+                        self.pending.scroll_lines.1 += y;
+                    }
+                    MouseScrollDelta::PixelDelta(position) => {
+                        self.pending.scroll_pixels.0 += position.x;
+                        self.pending.scroll_pixels.1 += position.y;
+                    }
+                },
+                WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+                WindowEvent::CursorMoved { position, .. } => self.cursor_position = Some(*position),
+                WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+                WindowEvent::Focused(false) => self.release_all(),
+                _ => {}
+            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                self.pending.mouse_delta.0 += delta.0;
+                self.pending.mouse_delta.1 += delta.1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes the edges accumulated since the last step visible.
+    pub(crate) fn begin_step(&mut self) {
+        self.current = mem::take(&mut self.pending);
+    }
+
+    pub(crate) fn end_step(&mut self) {
+        self.current = InputEdges::default();
+    }
+
+    // release everything on focus loss to avoid stuck keys
+    fn release_all(&mut self) {
+        for key in self.keys_down.drain() {
+            self.pending.keys_released.insert(key);
+        }
+        for button in self.buttons_down.drain() {
+            self.pending.buttons_released.insert(button);
+        }
+        self.modifiers = ModifiersState::empty();
+    }
+}
+
+// key tables
+
+macro_rules! keys {
+    ($($key:ident),*) => {
+        const KEYS: &[VirtualKeyCode] = &[$(VirtualKeyCode::$key),*];
+        const KEY_NAMES: &[&str] = &[$(stringify!($key)),*];
+    };
+}
+
+keys! {
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J, K, L,
+    M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11,
+    F12, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, Snapshot, Scroll, Pause,
+    Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space,
+    Compose, Caret, Numlock, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6,
+    Numpad7, Numpad8, Numpad9, NumpadAdd, NumpadDivide, NumpadDecimal, NumpadComma, NumpadEnter,
+    NumpadEquals, NumpadMultiply, NumpadSubtract, AbntC1, AbntC2, Apostrophe, Apps, Asterisk, At,
+    Ax, Backslash, Calculator, Capital, Colon, Comma, Convert, Equals, Grave, Kana, Kanji, LAlt,
+    LBracket, LControl, LShift, LWin, Mail, MediaSelect, MediaStop, Minus, Mute, MyComputer,
+    NavigateForward, NavigateBackward, NextTrack, NoConvert, OEM102, Period, PlayPause, Plus,
+    Power, PrevTrack, RAlt, RBracket, RControl, RShift, RWin, Semicolon, Slash, Sleep, Stop, Sysrq,
+    Tab, Underline, Unlabeled, VolumeDown, VolumeUp, Wake, WebBack, WebFavorites, WebForward,
+    WebHome, WebRefresh, WebSearch, WebStop, Yen, Copy, Paste, Cut
+}
+
+/// Returns a key from its `VirtualKeyCode` variant name.
+pub(crate) fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    KEY_NAMES
+        .iter()
+        .position(|key_name| *key_name == name)
+        .map(|index| KEYS[index])
+}
+
+/// Returns a key from its `VirtualKeyCode` discriminant.
+pub(crate) fn key_from_code(code: u32) -> Option<VirtualKeyCode> {
+    KEYS.iter().copied().find(|key| *key as u32 == code)
+}
@@ -0,0 +1,440 @@
+//! A work-stealing job system run by the game loop (see `GameContext::jobs`).
+//!
+//! Job timings are reported in `GameStats::jobs`; there is no trace exporter.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// how long an idle worker sleeps before looking for work again
+const IDLE_TIMEOUT: time::Duration = time::Duration::from_millis(1);
+
+thread_local! {
+    // (pool address, worker index) of the worker running on this thread
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // jobs run outside of the locks, a poisoned lock is still consistent
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// JobStats
+
+/// Job timings, accumulated until taken (see `GameStats::jobs`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JobStats {
+    /// Number of jobs run.
+    pub job_count: u64,
+    /// Total time spent running jobs (on all threads).
+    pub job_time: time::Duration,
+    /// Time spent in scopes, waiting for their jobs to complete.
+    pub scope_time: time::Duration,
+}
+
+// Pool
+
+struct Pool {
+    // jobs pushed from outside the workers
+    injector: Mutex<VecDeque<Job>>,
+    // per worker deques: the owner pops from the back, thieves from the front
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+    stats: Mutex<JobStats>,
+}
+
+impl Pool {
+    fn current_worker(&self) -> Option<usize> {
+        let address = self as *const Pool as usize;
+        WORKER.with(|worker| match worker.get() {
+            Some((pool, index)) if pool == address => Some(index),
+            _ => None,
+        })
+    }
+
+    fn push(&self, job: Job) {
+        match self.current_worker() {
+            Some(index) => lock(&self.queues[index]).push_back(job),
+            None => lock(&self.injector).push_back(job),
+        }
+        self.wake.notify_one();
+    }
+
+    fn pop(&self, worker: Option<usize>) -> Option<Job> {
+        if let Some(index) = worker {
+            if let Some(job) = lock(&self.queues[index]).pop_back() {
+                return Some(job);
+            }
+        }
+        if let Some(job) = lock(&self.injector).pop_front() {
+            return Some(job);
+        }
+        // steal
+        let count = self.queues.len();
+        let start = worker.map_or(0, |index| index + 1);
+        (0..count)
+            .map(|i| (start + i) % count)
+            .filter(|&index| Some(index) != worker)
+            .find_map(|index| lock(&self.queues[index]).pop_front())
+    }
+
+    fn run(&self, job: Job) {
+        let start_time = time::Instant::now();
+        job();
+        let job_time = start_time.elapsed();
+        let mut stats = lock(&self.stats);
+        stats.job_count += 1;
+        stats.job_time += job_time;
+    }
+}
+
+fn work(pool: Arc<Pool>, index: usize) {
+    WORKER.with(|worker| worker.set(Some((Arc::as_ptr(&pool) as usize, index))));
+    while !pool.shutdown.load(Ordering::Acquire) {
+        match pool.pop(Some(index)) {
+            Some(job) => pool.run(job),
+            None => {
+                let guard = lock(&pool.sleep);
+                let _ = pool.wake.wait_timeout(guard, IDLE_TIMEOUT);
+            }
+        }
+    }
+}
+
+// JobHandle
+
+#[derive(Default)]
+struct JobNode {
+    done: bool,
+    dependents: Vec<Arc<Deferred>>,
+}
+
+// a job waiting for its dependencies
+struct Deferred {
+    remaining: AtomicUsize,
+    job: Mutex<Option<Job>>,
+}
+
+impl Deferred {
+    fn release(&self, pool: &Pool) {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            if let Some(job) = lock(&self.job).take() {
+                pool.push(job);
+            }
+        }
+    }
+}
+
+/// A spawned job, that other jobs can depend on (see `Scope::spawn_after`).
+#[derive(Clone)]
+pub struct JobHandle {
+    node: Arc<Mutex<JobNode>>,
+}
+
+impl JobHandle {
+    pub fn is_done(&self) -> bool {
+        lock(&self.node).done
+    }
+}
+
+// Scope
+
+struct ScopeState {
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// Spawns jobs that can borrow from the enclosing stack frame (see `JobSystem::scope`).
+pub struct Scope<'env> {
+    pool: Arc<Pool>,
+    state: Arc<ScopeState>,
+    // invariant in 'env
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'env> Scope<'env> {
+    pub fn spawn<F>(&self, f: F) -> JobHandle
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        self.spawn_after(&[], f)
+    }
+
+    /// Spawns a job that runs once all its dependencies are done.
+    pub fn spawn_after<F>(&self, dependencies: &[JobHandle], f: F) -> JobHandle
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        let node = Arc::new(Mutex::new(JobNode::default()));
+        self.state.pending.fetch_add(1, Ordering::AcqRel);
+        let job = {
+            let pool = self.pool.clone();
+            let state = self.state.clone();
+            let node = node.clone();
+            Box::new(move || {
+                if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                    lock(&state.panic).get_or_insert(panic);
+                }
+                let dependents = {
+                    let mut node = lock(&node);
+                    node.done = true;
+                    mem::take(&mut node.dependents)
+                };
+                for dependent in dependents {
+                    dependent.release(&pool);
+                }
+                state.pending.fetch_sub(1, Ordering::AcqRel);
+            }) as Box<dyn FnOnce() + Send + 'env>
+        };
+        // SAFETY: the scope does not return before all its jobs are done
+        let job: Job = unsafe { mem::transmute(job) };
+
+        // one extra count until all dependencies are registered
+        let deferred = Arc::new(Deferred {
+            remaining: AtomicUsize::new(1),
+            job: Mutex::new(Some(job)),
+        });
+        for dependency in dependencies {
+            let mut dependency = lock(&dependency.node);
+            if !dependency.done {
+                deferred.remaining.fetch_add(1, Ordering::AcqRel);
+                dependency.dependents.push(deferred.clone());
+            }
+        }
+        deferred.release(&self.pool);
+        JobHandle { node }
+    }
+
+    // runs jobs until all the jobs of the scope are done
+    fn wait(&self) {
+        let worker = self.pool.current_worker();
+        while self.state.pending.load(Ordering::Acquire) > 0 {
+            match self.pool.pop(worker) {
+                Some(job) => self.pool.run(job),
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
+
+// JobSystem
+
+/// A work-stealing job system, to spread work across cores within a fixed step.
+///
+/// The thread calling `scope` helps running jobs until they are all done.
+/// Without worker threads, jobs run on the calling thread, in spawn order,
+/// at the end of the scope (this is used for deterministic replays).
+pub struct JobSystem {
+    pool: Arc<Pool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl JobSystem {
+    /// Creates a job system with `thread_count` worker threads
+    /// (single-threaded if `thread_count` is 0).
+    pub fn new(thread_count: usize) -> JobSystem {
+        let pool = Arc::new(Pool {
+            injector: Mutex::new(VecDeque::new()),
+            queues: (0..thread_count)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            stats: Mutex::new(JobStats::default()),
+        });
+        let threads = (0..thread_count)
+            .map(|index| {
+                let pool = pool.clone();
+                thread::Builder::new()
+                    .name(format!("job worker {}", index))
+                    .spawn(move || work(pool, index))
+                    .expect("failed to spawn job worker")
+            })
+            .collect();
+        JobSystem { pool, threads }
+    }
+
+    pub fn single_threaded() -> JobSystem {
+        Self::new(0)
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs `f`, then waits for all the jobs it spawned (which can borrow local data).
+    /// A panic in a job is propagated once all jobs are done.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'env>) -> R,
+    {
+        let start_time = time::Instant::now();
+        let scope = Scope {
+            pool: self.pool.clone(),
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+            }),
+            _env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // jobs borrow from the caller: wait for them even if f panicked
+        scope.wait();
+        lock(&self.pool.stats).scope_time += start_time.elapsed();
+        let result = match result {
+            Ok(result) => result,
+            Err(panic) => panic::resume_unwind(panic),
+        };
+        if let Some(panic) = lock(&scope.state.panic).take() {
+            panic::resume_unwind(panic);
+        }
+        result
+    }
+
+    /// Calls `f` with the index of each item, in parallel.
+    pub fn parallel_for<T, F>(&self, items: &mut [T], f: F)
+    where
+        T: Send,
+        F: Fn(usize, &mut T) + Sync,
+    {
+        // a few chunks per thread to balance the load
+        let chunk_count = (self.thread_count() * 4).max(1);
+        let chunk_size = items.len().div_ceil(chunk_count).max(1);
+        let f = &f;
+        self.scope(|scope| {
+            for (chunk_index, chunk) in items.chunks_mut(chunk_size).enumerate() {
+                scope.spawn(move || {
+                    for (i, item) in chunk.iter_mut().enumerate() {
+                        f(chunk_index * chunk_size + i, item);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Returns the job timings accumulated since the last call.
+    pub fn take_stats(&self) -> JobStats {
+        mem::take(&mut *lock(&self.pool.stats))
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        self.pool.shutdown.store(true, Ordering::Release);
+        self.pool.wake.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn jobs_run_after_their_dependencies() {
+        for &thread_count in &[0, 4] {
+            let jobs = JobSystem::new(thread_count);
+            for _ in 0..20 {
+                let order = Mutex::new(Vec::new());
+                let log = |name| {
+                    let order = &order;
+                    move || lock(order).push(name)
+                };
+                jobs.scope(|scope| {
+                    let a = scope.spawn(log("a"));
+                    let b = scope.spawn_after(std::slice::from_ref(&a), log("b"));
+                    let c = scope.spawn_after(std::slice::from_ref(&a), log("c"));
+                    scope.spawn_after(&[b, c.clone()], log("d"));
+                    scope.spawn_after(&[c], log("e"));
+                });
+                let order = order.into_inner().unwrap();
+                let position = |name| order.iter().position(|n| *n == name).unwrap();
+                assert_eq!(order.len(), 5);
+                assert!(position("a") < position("b"));
+                assert!(position("a") < position("c"));
+                assert!(position("b") < position("d"));
+                assert!(position("c") < position("d"));
+                assert!(position("c") < position("e"));
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_for_visits_each_item_once() {
+        for &thread_count in &[0, 3] {
+            let jobs = JobSystem::new(thread_count);
+            for &len in &[0, 1, 7, 1000] {
+                let mut items: Vec<(usize, u32)> = (0..len).map(|i| (i, 0)).collect();
+                jobs.parallel_for(&mut items, |index, item| {
+                    assert_eq!(index, item.0);
+                    item.1 += 1;
+                });
+                assert!(items.iter().all(|item| item.1 == 1));
+            }
+        }
+    }
+
+    #[test]
+    fn job_panics_propagate_once_the_scope_is_done() {
+        for &thread_count in &[0, 2] {
+            let jobs = JobSystem::new(thread_count);
+            let count = AtomicU32::new(0);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                jobs.scope(|scope| {
+                    for i in 0..10 {
+                        let count = &count;
+                        scope.spawn(move || {
+                            if i == 3 {
+                                panic!("job panicked");
+                            }
+                            count.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                })
+            }));
+            let panic = result.unwrap_err();
+            assert_eq!(panic.downcast_ref::<&str>(), Some(&"job panicked"));
+            assert_eq!(count.load(Ordering::Relaxed), 9);
+            // still usable
+            assert_eq!(jobs.scope(|_| 1), 1);
+        }
+    }
+
+    #[test]
+    fn single_threaded_jobs_run_in_order_on_the_caller() {
+        let jobs = JobSystem::single_threaded();
+        assert_eq!(jobs.thread_count(), 0);
+        let caller = thread::current().id();
+        let order = Mutex::new(Vec::new());
+        jobs.scope(|scope| {
+            for i in 0..10 {
+                let order = &order;
+                scope.spawn(move || {
+                    assert_eq!(thread::current().id(), caller);
+                    lock(order).push(i);
+                });
+            }
+            // jobs run at the end of the scope
+            assert!(lock(&order).is_empty());
+        });
+        assert_eq!(order.into_inner().unwrap(), (0..10).collect::<Vec<_>>());
+        let stats = jobs.take_stats();
+        assert_eq!(stats.job_count, 10);
+        assert_eq!(jobs.take_stats().job_count, 0);
+    }
+}
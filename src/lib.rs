@@ -2,4 +2,28 @@
 
 pub mod utility;
 
+pub mod action;
+pub mod clipboard;
+pub mod cursor;
+pub mod display;
+#[cfg(feature = "hecs")]
+pub mod ecs;
+pub mod executor;
+pub mod extrapolate;
 pub mod game_loop;
+pub mod gamepad;
+pub mod input;
+pub mod jobs;
+pub mod lockstep;
+pub mod net;
+pub mod render_thread;
+pub mod replay;
+pub mod rollback;
+pub mod scene;
+pub mod scheduler;
+pub mod server;
+pub mod simulation;
+pub mod snapshot;
+pub mod text_input;
+pub mod timers;
+pub mod window_state;
@@ -0,0 +1,413 @@
+use std::collections::VecDeque;
+use std::thread;
+use std::time;
+
+use crate::game_loop::Tick;
+use crate::net::{InputExchange, LoopbackTransport, NetInput, Transport};
+
+// number of local checksums kept to check the remote ones
+const CHECKSUM_HISTORY: usize = 256;
+// time without any player progressing after which the harness gives up (plus the latency)
+const STALL_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+// LockstepState
+
+/// The deterministic part of a game, simulated with the inputs of all players
+/// (see `LockstepSession`).
+pub trait LockstepState {
+    type Input: NetInput;
+
+    /// Runs a fixed step, `inputs` holds the input of each player.
+    fn update_fixed_step(&mut self, inputs: &[Self::Input], tick: Tick, dt: time::Duration);
+    /// Returns a checksum of the state, compared with the other players to detect desyncs.
+    fn checksum(&self) -> u64;
+}
+
+/// The states of two players diverged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Desync {
+    /// Tick after which the checksums differ.
+    pub tick: Tick,
+    pub player: usize,
+    pub local_checksum: u64,
+    pub remote_checksum: u64,
+}
+
+// LockstepStats
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LockstepStats {
+    /// Next tick to be simulated.
+    pub tick: u64,
+    /// Number of fixed steps skipped waiting for remote inputs.
+    pub waiting_steps: u64,
+    /// Number of remote checksums checked.
+    pub checked_checksums: u64,
+}
+
+// LockstepSession
+
+/// Deterministic lockstep networking: a tick is simulated only once the inputs
+/// of all players for that tick are known.
+///
+/// `advance` is called from `Game::update_fixed_step`, in place of the game's own
+/// simulation. With `GameLoop::set_lockstep`, `is_ready` is called from
+/// `Game::lockstep_ready` so that the loop runs the fixed steps (and fires the timers)
+/// only once the next tick can be simulated: the loop ticks are the session ticks.
+/// Otherwise the fixed steps only pace the session, which skips them while waiting for inputs.
+/// After each tick the state checksum is sent to the other players, a mismatch
+/// is reported as a desync. All players must use the same input delay and update period.
+pub struct LockstepSession<I: NetInput, T: Transport> {
+    transport: T,
+    exchange: InputExchange<I>,
+    input_delay: u64,
+    // next tick to be simulated
+    tick: u64,
+    // (tick, checksum) of the last local ticks
+    checksums: VecDeque<(u64, u64)>,
+    // (player, tick, checksum) received ahead of the local simulation
+    remote_checksums: Vec<(usize, u64, u64)>,
+    desync: Option<Desync>,
+    stats: LockstepStats,
+}
+
+impl<I: NetInput, T: Transport> LockstepSession<I, T> {
+    /// `input_delay` ticks are added to the local inputs, which hides the latency
+    /// of up to as many ticks (without it, every tick waits for the remote inputs).
+    pub fn new(
+        transport: T,
+        player_count: usize,
+        local_player: usize,
+        input_delay: u64,
+    ) -> LockstepSession<I, T> {
+        LockstepSession {
+            transport,
+            exchange: InputExchange::new(player_count, local_player, input_delay),
+            input_delay,
+            tick: 0,
+            checksums: VecDeque::with_capacity(CHECKSUM_HISTORY),
+            remote_checksums: Vec::new(),
+            desync: None,
+            stats: LockstepStats::default(),
+        }
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.exchange.local_player()
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.exchange.player_count()
+    }
+
+    /// Returns the next tick to be simulated.
+    pub fn tick(&self) -> Tick {
+        Tick(self.tick)
+    }
+
+    /// Returns the first desync detected, if any.
+    pub fn desync(&self) -> Option<Desync> {
+        self.desync
+    }
+
+    pub fn stats(&self) -> LockstepStats {
+        self.stats
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Exchanges inputs without simulating (e.g. while paused or once done),
+    /// so that the other players still get the local inputs.
+    pub fn poll(&mut self) {
+        let received = self.exchange.receive(&mut self.transport);
+        self.remote_checksums.extend(received.checksums);
+        self.check_checksums();
+        let checksum = self.checksums.back().copied();
+        self.exchange.send(&mut self.transport, checksum);
+    }
+
+    /// Exchanges inputs, returns true if the inputs of all players for the next tick
+    /// are known (then `advance` simulates it).
+    /// The local input is applied `input_delay` ticks later.
+    pub fn is_ready(&mut self, local_input: I) -> bool {
+        self.exchange_inputs(local_input).is_some()
+    }
+
+    /// Exchanges inputs, then simulates the next tick if the inputs of all players
+    /// are known. The local input is applied `input_delay` ticks later.
+    /// Returns false if the step was skipped, waiting for remote inputs.
+    pub fn advance<S>(&mut self, state: &mut S, local_input: I, dt: time::Duration) -> bool
+    where
+        S: LockstepState<Input = I>,
+    {
+        let inputs = match self.exchange_inputs(local_input) {
+            Some(inputs) => inputs,
+            None => return false,
+        };
+
+        state.update_fixed_step(&inputs, Tick(self.tick), dt);
+        if self.checksums.len() >= CHECKSUM_HISTORY {
+            self.checksums.pop_front();
+        }
+        let checksum = (self.tick, state.checksum());
+        self.checksums.push_back(checksum);
+        self.tick += 1;
+        self.exchange.prune(self.tick);
+        self.exchange.send(&mut self.transport, Some(checksum));
+        self.check_checksums();
+
+        self.stats.tick = self.tick;
+        true
+    }
+
+    // returns the inputs of the next tick if known, otherwise sends the local ones again
+    fn exchange_inputs(&mut self, local_input: I) -> Option<Vec<I>> {
+        self.exchange
+            .set_local_input(self.tick + self.input_delay, local_input);
+        let received = self.exchange.receive(&mut self.transport);
+        self.remote_checksums.extend(received.checksums);
+        self.check_checksums();
+
+        let inputs = self.exchange.inputs(self.tick);
+        if inputs.is_none() {
+            self.stats.waiting_steps += 1;
+            let checksum = self.checksums.back().copied();
+            self.exchange.send(&mut self.transport, checksum);
+        }
+        inputs
+    }
+
+    // compares the remote checksums with the local ones, keeps those ahead of the simulation
+    fn check_checksums(&mut self) {
+        let (checksums, tick) = (&self.checksums, self.tick);
+        let mut checked = 0;
+        let mut desync = None;
+        self.remote_checksums
+            .retain(|&(player, remote_tick, remote_checksum)| {
+                if remote_tick >= tick {
+                    return true;
+                }
+                // too old checksums are dropped
+                if let Some(&(_, local_checksum)) =
+                    checksums.iter().find(|(t, _)| *t == remote_tick)
+                {
+                    checked += 1;
+                    if local_checksum != remote_checksum && desync.is_none() {
+                        desync = Some(Desync {
+                            tick: Tick(remote_tick),
+                            player,
+                            local_checksum,
+                            remote_checksum,
+                        });
+                    }
+                }
+                false
+            });
+        self.stats.checked_checksums += checked;
+        if self.desync.is_none() {
+            self.desync = desync;
+        }
+    }
+}
+
+// LockstepHarness
+
+/// Why `LockstepHarness::run` stopped before the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HarnessError {
+    /// A player (the first field) detected a desync.
+    Desync(usize, Desync),
+    /// No player progressed for a while (e.g. all the packets of a player are lost),
+    /// the field is the tick of the players the furthest behind.
+    Stalled(Tick),
+}
+
+/// Runs several lockstep players in-process, connected through loopback transports,
+/// to test that a game stays in sync.
+pub struct LockstepHarness<S: LockstepState> {
+    players: Vec<(LockstepSession<S::Input, LoopbackTransport>, S)>,
+    dt: time::Duration,
+    stall_timeout: time::Duration,
+}
+
+impl<S: LockstepState> LockstepHarness<S> {
+    /// Creates a player per state, with the given one-way latency between players.
+    pub fn new(states: Vec<S>, input_delay: u64, latency: time::Duration) -> LockstepHarness<S> {
+        let player_count = states.len();
+        let players = LoopbackTransport::network(player_count, latency)
+            .into_iter()
+            .zip(states)
+            .enumerate()
+            .map(|(player, (transport, state))| {
+                let session = LockstepSession::new(transport, player_count, player, input_delay);
+                (session, state)
+            })
+            .collect();
+        LockstepHarness {
+            players,
+            dt: time::Duration::from_secs_f32(1.0 / 60.0),
+            stall_timeout: STALL_TIMEOUT + latency,
+        }
+    }
+
+    /// Sets the `dt` given to the fixed steps (1/60 s by default).
+    pub fn set_update_period(&mut self, dt: time::Duration) {
+        self.dt = dt;
+    }
+
+    pub fn session(&mut self, player: usize) -> &mut LockstepSession<S::Input, LoopbackTransport> {
+        &mut self.players[player].0
+    }
+
+    pub fn state(&self, player: usize) -> &S {
+        &self.players[player].1
+    }
+
+    /// Runs all players until they have simulated `tick_count` ticks, or until a desync.
+    /// `input` gives the input of a player when simulating a tick
+    /// (it is applied `input_delay` ticks later).
+    /// Returns the first desync, with the player that detected it,
+    /// or an error if the players stop progressing.
+    pub fn run<F>(&mut self, tick_count: u64, mut input: F) -> Result<(), HarnessError>
+    where
+        F: FnMut(usize, Tick) -> S::Input,
+    {
+        let mut progress_time = time::Instant::now();
+        loop {
+            let mut done = true;
+            let mut progress = false;
+            for (player, (session, state)) in self.players.iter_mut().enumerate() {
+                if session.tick().0 < tick_count {
+                    let local_input = input(player, session.tick());
+                    progress |= session.advance(state, local_input, self.dt);
+                    done = false;
+                } else {
+                    // the others may still miss inputs
+                    session.poll();
+                }
+                if let Some(desync) = session.desync() {
+                    return Err(HarnessError::Desync(player, desync));
+                }
+            }
+            if done {
+                return Ok(());
+            }
+            if progress {
+                progress_time = time::Instant::now();
+            } else if progress_time.elapsed() > self.stall_timeout {
+                let tick = self.players.iter().map(|(session, _)| session.tick());
+                return Err(HarnessError::Stalled(tick.min().unwrap_or_default()));
+            } else {
+                // waiting for packets in flight
+                thread::sleep(time::Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a state that diverges on `bug_tick`, if any
+    #[derive(Default)]
+    struct State {
+        value: u64,
+        bug_tick: Option<u64>,
+    }
+
+    impl LockstepState for State {
+        type Input = u8;
+
+        fn update_fixed_step(&mut self, inputs: &[u8], tick: Tick, _dt: time::Duration) {
+            for (player, input) in inputs.iter().enumerate() {
+                self.value = self.value.wrapping_mul(31) ^ (*input as u64 * (player as u64 + 1));
+            }
+            if self.bug_tick == Some(tick.0) {
+                self.value += 1;
+            }
+        }
+
+        fn checksum(&self) -> u64 {
+            self.value
+        }
+    }
+
+    fn input(player: usize, tick: Tick) -> u8 {
+        ((tick.0 * 7 + player as u64 * 3) % 5) as u8
+    }
+
+    #[test]
+    fn peers_stay_in_sync() {
+        for player_count in 2..=3 {
+            let states = (0..player_count).map(|_| State::default()).collect();
+            let mut harness = LockstepHarness::new(states, 2, time::Duration::from_millis(2));
+            assert_eq!(harness.run(100, input), Ok(()));
+            let checksum = harness.state(0).checksum();
+            for player in 0..player_count {
+                assert_eq!(harness.session(player).tick(), Tick(100));
+                assert_eq!(harness.state(player).checksum(), checksum);
+                assert!(harness.session(player).stats().checked_checksums > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn ready_once_remote_inputs_are_known() {
+        let mut sessions: Vec<LockstepSession<u8, _>> =
+            LoopbackTransport::network(2, time::Duration::new(0, 0))
+                .into_iter()
+                .enumerate()
+                .map(|(player, transport)| LockstepSession::new(transport, 2, player, 0))
+                .collect();
+        let mut states = [State::default(), State::default()];
+        let dt = time::Duration::from_millis(16);
+        assert!(!sessions[0].is_ready(1));
+        // the local inputs are sent while waiting
+        assert!(sessions[1].is_ready(2));
+        assert!(sessions[1].advance(&mut states[1], 2, dt));
+        assert!(sessions[0].is_ready(1));
+        assert!(sessions[0].advance(&mut states[0], 1, dt));
+        assert_eq!(sessions[0].tick(), Tick(1));
+        assert_eq!(sessions[0].stats().waiting_steps, 1);
+        assert_eq!(states[0].checksum(), states[1].checksum());
+    }
+
+    #[test]
+    fn diverging_state_is_a_desync() {
+        let states = vec![
+            State::default(),
+            State {
+                value: 0,
+                bug_tick: Some(40),
+            },
+            State::default(),
+        ];
+        let mut harness = LockstepHarness::new(states, 2, time::Duration::from_millis(2));
+        let (player, desync) = match harness.run(100, input) {
+            Err(HarnessError::Desync(player, desync)) => (player, desync),
+            result => panic!("unexpected result {:?}", result),
+        };
+        assert_eq!(desync.tick, Tick(40));
+        assert!(desync.player != player);
+        assert!(player == 1 || desync.player == 1);
+        assert_ne!(desync.local_checksum, desync.remote_checksum);
+    }
+
+    #[test]
+    fn lost_peer_stalls_the_harness() {
+        let states = (0..2).map(|_| State::default()).collect();
+        let mut harness = LockstepHarness::new(states, 2, time::Duration::from_millis(2));
+        assert_eq!(harness.run(10, input), Ok(()));
+        harness.session(1).transport().set_loss(1.0);
+        let start = time::Instant::now();
+        // player 0 runs out of player 1 inputs after the input delay
+        assert_eq!(
+            harness.run(100, input),
+            Err(HarnessError::Stalled(Tick(12)))
+        );
+        assert!(start.elapsed() < STALL_TIMEOUT * 3);
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time;
+
+// large enough for any packet sent by the sessions
+const MAX_PACKET_SIZE: usize = 1500;
+
+// NetInput
+
+/// A player input for one tick, exchanged with the other peers.
+pub trait NetInput: Copy + Default + PartialEq + 'static {
+    /// Encoded size, in bytes.
+    const SIZE: usize;
+
+    fn encode(&self, buffer: &mut [u8]);
+    fn decode(buffer: &[u8]) -> Self;
+}
+
+macro_rules! impl_net_input {
+    ($($t:ty),*) => {
+        $(
+            impl NetInput for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn encode(&self, buffer: &mut [u8]) {
+                    buffer[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(buffer: &[u8]) -> Self {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    bytes.copy_from_slice(&buffer[..Self::SIZE]);
+                    <$t>::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_net_input!(u8, u16, u32, u64);
+
+// Transport
+
+/// Sends and receives packets to and from the other players (identified by their index).
+///
+/// Packets may be lost, the sessions send inputs redundantly until they are acknowledged.
+pub trait Transport {
+    fn send(&mut self, player: usize, packet: &[u8]) -> io::Result<()>;
+    /// Returns the next received packet and the player who sent it, without blocking.
+    fn receive(&mut self) -> io::Result<Option<(usize, Vec<u8>)>>;
+}
+
+// UdpTransport
+
+/// A transport over a non-blocking UDP socket.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    // player addresses (the local player's one is not used)
+    peers: Vec<SocketAddr>,
+    buffer: Vec<u8>,
+}
+
+impl UdpTransport {
+    /// Binds the socket to `address`; `peers` gives the address of each player.
+    pub fn bind(address: SocketAddr, peers: Vec<SocketAddr>) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport {
+            socket,
+            peers,
+            buffer: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+
+    pub fn local_address(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, player: usize, packet: &[u8]) -> io::Result<()> {
+        match self.socket.send_to(packet, self.peers[player]) {
+            Ok(_) => Ok(()),
+            // dropped, as if lost
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(usize, Vec<u8>)>> {
+        loop {
+            let (size, address) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                // reported on some platforms when a peer is not (yet) listening
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e),
+            };
+            // packets from unknown addresses are ignored
+            if let Some(player) = self.peers.iter().position(|peer| *peer == address) {
+                return Ok(Some((player, self.buffer[..size].to_vec())));
+            }
+        }
+    }
+}
+
+// LoopbackTransport
+
+struct InFlight {
+    delivery_time: time::Instant,
+    player: usize,
+    packet: Vec<u8>,
+}
+
+/// An in-process transport with simulated latency and packet loss,
+/// to test networked games on a single machine.
+pub struct LoopbackTransport {
+    player: usize,
+    // inboxes of all players
+    inboxes: Arc<Mutex<Vec<VecDeque<InFlight>>>>,
+    latency: time::Duration,
+    loss: f32,
+    // xorshift state, for reproducible packet loss
+    random: u64,
+}
+
+impl LoopbackTransport {
+    /// Creates the transports of `player_count` connected players.
+    pub fn network(player_count: usize, latency: time::Duration) -> Vec<LoopbackTransport> {
+        let inboxes = Arc::new(Mutex::new(
+            (0..player_count).map(|_| VecDeque::new()).collect(),
+        ));
+        (0..player_count)
+            .map(|player| LoopbackTransport {
+                player,
+                inboxes: inboxes.clone(),
+                latency,
+                loss: 0.0,
+                random: 0x9E37_79B9_7F4A_7C15 ^ (player as u64 + 1),
+            })
+            .collect()
+    }
+
+    /// Sets the one-way latency of the packets sent by this player.
+    pub fn set_latency(&mut self, latency: time::Duration) {
+        self.latency = latency;
+    }
+
+    /// Sets the ratio, in [0.0, 1.0], of the packets sent by this player that are lost.
+    pub fn set_loss(&mut self, loss: f32) {
+        self.loss = loss;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<VecDeque<InFlight>>> {
+        self.inboxes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn next_random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (self.random >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, player: usize, packet: &[u8]) -> io::Result<()> {
+        if self.loss > 0.0 && self.next_random() < self.loss {
+            return Ok(());
+        }
+        let in_flight = InFlight {
+            delivery_time: time::Instant::now() + self.latency,
+            player: self.player,
+            packet: packet.to_vec(),
+        };
+        self.lock()[player].push_back(in_flight);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(usize, Vec<u8>)>> {
+        let now = time::Instant::now();
+        let mut inboxes = self.lock();
+        let inbox = &mut inboxes[self.player];
+        // packets from a player are delivered in order
+        match inbox.iter().position(|p| p.delivery_time <= now) {
+            Some(index) => Ok(inbox
+                .remove(index)
+                .map(|in_flight| (in_flight.player, in_flight.packet))),
+            None => Ok(None),
+        }
+    }
+}
+
+// InputExchange
+
+// inputs sent again in each packet until acknowledged (the count is a byte)
+const MAX_INPUTS_PER_PACKET: usize = 64;
+// player index, ack, checksum tick, checksum, first tick, input count
+const HEADER_SIZE: usize = 1 + 8 + 8 + 8 + 8 + 1;
+// inputs further ahead of the first missing one are rejected (corrupt or malicious packets)
+const MAX_INPUTS_AHEAD: u64 = 4 * MAX_INPUTS_PER_PACKET as u64;
+// checksum tick when there is no checksum
+const NO_CHECKSUM: u64 = u64::MAX;
+
+fn read_u64(buffer: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buffer[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Inputs received by `InputExchange::receive`.
+pub(crate) struct Received<I> {
+    /// (player, tick, input)
+    pub(crate) inputs: Vec<(usize, u64, I)>,
+    /// (player, tick, checksum)
+    pub(crate) checksums: Vec<(usize, u64, u64)>,
+}
+
+/// The per-tick inputs of all players, exchanged through a transport
+/// (shared by the rollback and lockstep sessions).
+pub(crate) struct InputExchange<I: NetInput> {
+    player_count: usize,
+    local_player: usize,
+    // known inputs of each player
+    inputs: Vec<BTreeMap<u64, I>>,
+    // per player, the first tick whose input is missing
+    received: Vec<u64>,
+    // per player, the first tick the player is missing from the local player
+    acked: Vec<u64>,
+}
+
+impl<I: NetInput> InputExchange<I> {
+    pub(crate) fn new(player_count: usize, local_player: usize, input_delay: u64) -> Self {
+        assert!(local_player < player_count, "invalid local player");
+        assert!(player_count <= u8::MAX as usize, "too many players");
+        // nobody has an input for the ticks before the input delay
+        let inputs = (0..player_count)
+            .map(|_| (0..input_delay).map(|tick| (tick, I::default())).collect())
+            .collect();
+        InputExchange {
+            player_count,
+            local_player,
+            inputs,
+            received: vec![input_delay; player_count],
+            acked: vec![0; player_count],
+        }
+    }
+
+    pub(crate) fn player_count(&self) -> usize {
+        self.player_count
+    }
+
+    pub(crate) fn local_player(&self) -> usize {
+        self.local_player
+    }
+
+    /// Returns the first tick for which the input of a player is missing.
+    pub(crate) fn confirmed(&self) -> u64 {
+        self.received.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Returns the inputs of all players for `tick`, if they are all known.
+    pub(crate) fn inputs(&self, tick: u64) -> Option<Vec<I>> {
+        self.inputs
+            .iter()
+            .map(|inputs| inputs.get(&tick).copied())
+            .collect()
+    }
+
+    /// Returns the known inputs for `tick`, or the last known input of the missing players.
+    pub(crate) fn predicted_inputs(&self, tick: u64) -> Vec<I> {
+        self.inputs
+            .iter()
+            .map(|inputs| {
+                inputs
+                    .range(..=tick)
+                    .next_back()
+                    .map_or_else(I::default, |(_, input)| *input)
+            })
+            .collect()
+    }
+
+    /// Sets the local input of `tick`, unless already set.
+    pub(crate) fn set_local_input(&mut self, tick: u64, input: I) {
+        self.insert(self.local_player, tick, input);
+    }
+
+    fn insert(&mut self, player: usize, tick: u64, input: I) -> bool {
+        if tick < self.received[player] || self.inputs[player].contains_key(&tick) {
+            return false;
+        }
+        self.inputs[player].insert(tick, input);
+        while self.inputs[player].contains_key(&self.received[player]) {
+            self.received[player] += 1;
+        }
+        true
+    }
+
+    /// Forgets the inputs before `tick` (the last one is kept for predictions).
+    pub(crate) fn prune(&mut self, tick: u64) {
+        for inputs in &mut self.inputs {
+            let keep = inputs.range(..tick).next_back().map(|(tick, _)| *tick);
+            if let Some(keep) = keep {
+                *inputs = inputs.split_off(&keep);
+            }
+        }
+    }
+
+    /// Sends the local inputs not acknowledged yet to the other players,
+    /// with the checksum of the state at a given tick.
+    pub(crate) fn send<T: Transport>(&self, transport: &mut T, checksum: Option<(u64, u64)>) {
+        let local = &self.inputs[self.local_player];
+        let last = self.received[self.local_player];
+        let (checksum_tick, checksum) = checksum.unwrap_or((NO_CHECKSUM, 0));
+        for player in 0..self.player_count {
+            if player == self.local_player {
+                continue;
+            }
+            let first = self.acked[player]
+                .max(last.saturating_sub(MAX_INPUTS_PER_PACKET as u64))
+                .min(last);
+            let count = (last - first) as usize;
+            let mut packet = vec![0; HEADER_SIZE + count * I::SIZE];
+            packet[0] = self.local_player as u8;
+            packet[1..9].copy_from_slice(&self.received[player].to_le_bytes());
+            packet[9..17].copy_from_slice(&checksum_tick.to_le_bytes());
+            packet[17..25].copy_from_slice(&checksum.to_le_bytes());
+            packet[25..33].copy_from_slice(&first.to_le_bytes());
+            packet[33] = count as u8;
+            for (i, tick) in (first..last).enumerate() {
+                let input = local.get(&tick).copied().unwrap_or_default();
+                let offset = HEADER_SIZE + i * I::SIZE;
+                input.encode(&mut packet[offset..offset + I::SIZE]);
+            }
+            if let Err(e) = transport.send(player, &packet) {
+                eprintln!("failed to send inputs to player {} ({})", player, e);
+            }
+        }
+    }
+
+    /// Receives the pending packets, returns the new inputs and the checksums.
+    pub(crate) fn receive<T: Transport>(&mut self, transport: &mut T) -> Received<I> {
+        let mut received = Received {
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        };
+        loop {
+            let (player, packet) = match transport.receive() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to receive inputs ({})", e);
+                    break;
+                }
+            };
+            // malformed packets are ignored
+            if packet.len() < HEADER_SIZE
+                || player >= self.player_count
+                || player == self.local_player
+                || packet[0] as usize != player
+            {
+                continue;
+            }
+            let count = packet[33] as usize;
+            if packet.len() != HEADER_SIZE + count * I::SIZE {
+                continue;
+            }
+            // the inputs must follow the received ones, and can't be acked before being sent
+            let first = read_u64(&packet[25..]);
+            let ack = read_u64(&packet[1..]);
+            match first.checked_add(count as u64) {
+                Some(end) if end <= self.received[player] + MAX_INPUTS_AHEAD => {}
+                _ => continue,
+            }
+            if ack > self.received[self.local_player] {
+                continue;
+            }
+            self.acked[player] = self.acked[player].max(ack);
+            let checksum_tick = read_u64(&packet[9..]);
+            if checksum_tick != NO_CHECKSUM {
+                received
+                    .checksums
+                    .push((player, checksum_tick, read_u64(&packet[17..])));
+            }
+            for i in 0..count {
+                let tick = first + i as u64;
+                let offset = HEADER_SIZE + i * I::SIZE;
+                let input = I::decode(&packet[offset..offset + I::SIZE]);
+                if self.insert(player, tick, input) {
+                    received.inputs.push((player, tick, input));
+                }
+            }
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn loopback_delivers_after_latency() {
+        let latency = time::Duration::from_millis(30);
+        let mut transports = LoopbackTransport::network(2, latency);
+        let start_time = time::Instant::now();
+        transports[0].send(1, b"first").unwrap();
+        transports[0].send(1, b"second").unwrap();
+        assert_eq!(transports[1].receive().unwrap(), None);
+        // not sent to itself
+        assert_eq!(transports[0].receive().unwrap(), None);
+
+        let mut packets = Vec::new();
+        while packets.len() < 2 {
+            if let Some(packet) = transports[1].receive().unwrap() {
+                assert!(start_time.elapsed() >= latency);
+                packets.push(packet);
+            }
+            thread::sleep(time::Duration::from_millis(1));
+        }
+        assert_eq!(packets, [(0, b"first".to_vec()), (0, b"second".to_vec())]);
+    }
+
+    #[test]
+    fn loopback_loss_drops_packets() {
+        let mut transports = LoopbackTransport::network(2, time::Duration::new(0, 0));
+        transports[0].set_loss(1.0);
+        for _ in 0..100 {
+            transports[0].send(1, b"lost").unwrap();
+        }
+        transports[1].send(0, b"kept").unwrap();
+        assert_eq!(transports[1].receive().unwrap(), None);
+        assert_eq!(
+            transports[0].receive().unwrap(),
+            Some((1, b"kept".to_vec()))
+        );
+    }
+
+    // a packet from player 1 holding `count` inputs from `first`
+    fn packet(first: u64, count: u8) -> Vec<u8> {
+        let mut packet = vec![0; HEADER_SIZE + count as usize];
+        packet[0] = 1;
+        packet[9..17].copy_from_slice(&NO_CHECKSUM.to_le_bytes());
+        packet[25..33].copy_from_slice(&first.to_le_bytes());
+        packet[33] = count;
+        for i in 0..count as usize {
+            packet[HEADER_SIZE + i] = 7;
+        }
+        packet
+    }
+
+    #[test]
+    fn exchange_rejects_out_of_window_inputs() {
+        let mut transports = LoopbackTransport::network(2, time::Duration::new(0, 0));
+        let mut exchange = InputExchange::<u8>::new(2, 0, 0);
+        for first in [u64::MAX - 1, MAX_INPUTS_AHEAD] {
+            transports[1].send(0, &packet(first, 2)).unwrap();
+            assert!(exchange.receive(&mut transports[0]).inputs.is_empty());
+        }
+
+        transports[1].send(0, &packet(0, 2)).unwrap();
+        let received = exchange.receive(&mut transports[0]);
+        assert_eq!(received.inputs, [(1, 0, 7), (1, 1, 7)]);
+        exchange.set_local_input(0, 3);
+        assert_eq!(exchange.inputs(0), Some(vec![3, 7]));
+    }
+}
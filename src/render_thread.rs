@@ -0,0 +1,192 @@
+use std::io;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+// Renderer
+
+/// The render part of a game, run on a dedicated thread (see `RenderThread`).
+pub trait Renderer: Send + 'static {
+    /// Everything needed to render a frame, produced by the update on the event loop thread.
+    type Packet: Send + 'static;
+
+    fn render(&mut self, packet: Self::Packet);
+}
+
+// RenderStats
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// Number of frames rendered.
+    pub frame_count: u64,
+    /// Time the last packet waited in the queue before being rendered.
+    pub queue_latency: time::Duration,
+    /// Largest queue latency so far.
+    pub max_queue_latency: time::Duration,
+    /// Time spent rendering the last packet.
+    pub render_duration: time::Duration,
+    /// Time the event loop thread was blocked on the last submit (pipeline full).
+    pub submit_wait: time::Duration,
+    /// Number of packets submitted and not yet rendered.
+    pub frames_in_flight: usize,
+}
+
+struct Packet<P> {
+    packet: P,
+    submit_time: time::Instant,
+}
+
+// RenderThread
+
+/// Renders frames on a dedicated thread, one (or more) frames behind the update,
+/// so that waiting for the GPU does not block event processing.
+///
+/// `Game::render` submits a packet built from the game state, and blocks only when
+/// `depth` packets are already in flight.
+/// `GameLoop::set_render_pipeline` runs a render thread driven by the loop instead.
+pub struct RenderThread<R: Renderer> {
+    sender: Option<mpsc::SyncSender<Packet<R::Packet>>>,
+    stats: Arc<Mutex<RenderStats>>,
+    handle: Option<thread::JoinHandle<R>>,
+}
+
+impl<R: Renderer> RenderThread<R> {
+    /// Spawns the render thread; `depth` is the number of frames the render thread
+    /// can lag behind the update (1 to render a frame while the next one is updated).
+    pub fn spawn(renderer: R, depth: usize) -> io::Result<RenderThread<R>> {
+        assert!(depth > 0, "pipeline depth must not be zero");
+        // the packet being rendered is not in the channel anymore
+        let (sender, receiver) = mpsc::sync_channel(depth - 1);
+        let stats = Arc::new(Mutex::new(RenderStats::default()));
+        let thread_stats = stats.clone();
+        let handle = thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || run(renderer, receiver, thread_stats))?;
+        Ok(RenderThread {
+            sender: Some(sender),
+            stats,
+            handle: Some(handle),
+        })
+    }
+
+    /// Queues a packet for rendering, waits if the pipeline is full.
+    pub fn submit(&self, packet: R::Packet) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        self.lock_stats().frames_in_flight += 1;
+        let start_time = time::Instant::now();
+        let packet = Packet {
+            packet,
+            submit_time: start_time,
+        };
+        let sent = sender.send(packet).is_ok();
+        let mut stats = self.lock_stats();
+        stats.submit_wait = start_time.elapsed();
+        if !sent {
+            stats.frames_in_flight -= 1;
+            eprintln!("render thread is not running");
+        }
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        *self.lock_stats()
+    }
+
+    fn lock_stats(&self) -> std::sync::MutexGuard<'_, RenderStats> {
+        // stats stay usable even if the render thread panicked
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Renders the pending packets, stops the render thread and returns the renderer
+    /// (`None` if the render thread panicked).
+    pub fn stop(mut self) -> Option<R> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Option<R> {
+        // closing the channel stops the render thread
+        self.sender = None;
+        self.handle.take()?.join().ok()
+    }
+}
+
+impl<R: Renderer> Drop for RenderThread<R> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+fn run<R: Renderer>(
+    mut renderer: R,
+    receiver: mpsc::Receiver<Packet<R::Packet>>,
+    stats: Arc<Mutex<RenderStats>>,
+) -> R {
+    for Packet {
+        packet,
+        submit_time,
+    } in receiver
+    {
+        let start_time = time::Instant::now();
+        renderer.render(packet);
+        let render_duration = start_time.elapsed();
+
+        let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+        let queue_latency = start_time - submit_time;
+        stats.frame_count += 1;
+        stats.queue_latency = queue_latency;
+        stats.max_queue_latency = stats.max_queue_latency.max(queue_latency);
+        stats.render_duration = render_duration;
+        stats.frames_in_flight -= 1;
+    }
+    renderer
+}
+
+// RenderPipeline
+
+// a render thread driven by the game loop, with the renderer type erased
+pub(crate) trait RenderPipeline<P> {
+    fn submit(&self, packet: P);
+    fn stats(&self) -> RenderStats;
+}
+
+impl<R: Renderer> RenderPipeline<R::Packet> for RenderThread<R> {
+    fn submit(&self, packet: R::Packet) {
+        RenderThread::submit(self, packet)
+    }
+
+    fn stats(&self) -> RenderStats {
+        RenderThread::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder(Arc<Mutex<Vec<u32>>>);
+
+    impl Renderer for Recorder {
+        type Packet = u32;
+
+        fn render(&mut self, packet: u32) {
+            self.0.lock().unwrap().push(packet);
+        }
+    }
+
+    #[test]
+    fn pipeline_renders_packets_in_order() {
+        let rendered = Arc::new(Mutex::new(Vec::new()));
+        let pipeline: Box<dyn RenderPipeline<u32>> =
+            Box::new(RenderThread::spawn(Recorder(rendered.clone()), 2).unwrap());
+        for frame in 0..10u32 {
+            pipeline.submit(frame);
+        }
+        let stats = pipeline.stats();
+        assert!(stats.frames_in_flight <= 2);
+        drop(pipeline);
+        assert_eq!(*rendered.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    DeviceEvent, DeviceId, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
+    MouseScrollDelta, TouchPhase, VirtualKeyCode, WindowEvent,
+};
+use winit::window::WindowId;
+
+use crate::gamepad::{self, GamepadEvent};
+use crate::input;
+
+const MAGIC: &[u8; 4] = b"WGLR";
+const VERSION: u8 = 2;
+// tag of the record holding the tick the recording ended on
+const END_TAG: u8 = 255;
+
+// InputEvent
+
+/// An input event, in a form that can be recorded and replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Keyboard {
+        scancode: u32,
+        state: ElementState,
+        key: Option<VirtualKeyCode>,
+    },
+    ReceivedCharacter(char),
+    ModifiersChanged(ModifiersState),
+    MouseInput {
+        state: ElementState,
+        button: MouseButton,
+    },
+    MouseWheel(MouseScrollDelta),
+    CursorMoved(PhysicalPosition<f64>),
+    CursorEntered,
+    CursorLeft,
+    Focused(bool),
+    MouseMotion((f64, f64)),
+    Gamepad(GamepadEvent),
+}
+
+impl InputEvent {
+    /// Returns the input event to record, or `None` if the event is not an input event.
+    pub fn from_event<T>(event: &Event<'_, T>) -> Option<InputEvent> {
+        match event {
+            Event::WindowEvent { event, .. } => match *event {
+                WindowEvent::KeyboardInput { input, .. } => Some(InputEvent::Keyboard {
+                    scancode: input.scancode,
+                    state: input.state,
+                    key: input.virtual_keycode,
+                }),
+                WindowEvent::ReceivedCharacter(c) => Some(InputEvent::ReceivedCharacter(c)),
+                WindowEvent::ModifiersChanged(modifiers) => {
+                    Some(InputEvent::ModifiersChanged(modifiers))
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    Some(InputEvent::MouseInput { state, button })
+                }
+                WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::MouseWheel(delta)),
+                WindowEvent::CursorMoved { position, .. } => {
+                    Some(InputEvent::CursorMoved(position))
+                }
+                WindowEvent::CursorEntered { .. } => Some(InputEvent::CursorEntered),
+                WindowEvent::CursorLeft { .. } => Some(InputEvent::CursorLeft),
+                WindowEvent::Focused(focused) => Some(InputEvent::Focused(focused)),
+                _ => None,
+            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => Some(InputEvent::MouseMotion(*delta)),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds the winit event (device ids are dummies).
+    /// Returns `None` for gamepad events.
+    #[allow(deprecated)]
+    pub fn to_event<T>(&self, window_id: WindowId) -> Option<Event<'static, T>> {
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        let event = match *self {
+            InputEvent::Keyboard {
+                scancode,
+                state,
+                key,
+            } => WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode,
+                    state,
+                    virtual_keycode: key,
+                    modifiers,
+                },
+                is_synthetic: false,
+            },
+            InputEvent::ReceivedCharacter(c) => WindowEvent::ReceivedCharacter(c),
+            InputEvent::ModifiersChanged(modifiers) => WindowEvent::ModifiersChanged(modifiers),
+            InputEvent::MouseInput { state, button } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            },
+            InputEvent::MouseWheel(delta) => WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            InputEvent::CursorMoved(position) => WindowEvent::CursorMoved {
+                device_id,
+                position,
+                modifiers,
+            },
+            InputEvent::CursorEntered => WindowEvent::CursorEntered { device_id },
+            InputEvent::CursorLeft => WindowEvent::CursorLeft { device_id },
+            InputEvent::Focused(focused) => WindowEvent::Focused(focused),
+            InputEvent::MouseMotion(delta) => {
+                return Some(Event::DeviceEvent {
+                    device_id,
+                    event: DeviceEvent::MouseMotion { delta },
+                })
+            }
+            InputEvent::Gamepad(_) => return None,
+        };
+        Some(Event::WindowEvent { window_id, event })
+    }
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        match *self {
+            InputEvent::Keyboard {
+                scancode,
+                state,
+                key,
+            } => {
+                w.write_all(&[0])?;
+                write_varint(w, scancode.into())?;
+                write_state(w, state)?;
+                write_varint(w, key.map_or(0, |key| key as u64 + 1))
+            }
+            InputEvent::ReceivedCharacter(c) => {
+                w.write_all(&[1])?;
+                write_varint(w, u32::from(c).into())
+            }
+            InputEvent::ModifiersChanged(modifiers) => {
+                w.write_all(&[2])?;
+                write_varint(w, modifiers.bits().into())
+            }
+            InputEvent::MouseInput { state, button } => {
+                w.write_all(&[3])?;
+                write_state(w, state)?;
+                write_varint(
+                    w,
+                    match button {
+                        MouseButton::Left => 0,
+                        MouseButton::Right => 1,
+                        MouseButton::Middle => 2,
+                        MouseButton::Other(n) => 3 + u64::from(n),
+                    },
+                )
+            }
+            InputEvent::MouseWheel(MouseScrollDelta::LineDelta(x, y)) => {
+                w.write_all(&[4])?;
+                w.write_all(&x.to_le_bytes())?;
+                w.write_all(&y.to_le_bytes())
+            }
+            InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(position)) => {
+                w.write_all(&[5])?;
+                write_f64_pair(w, (position.x, position.y))
+            }
+            InputEvent::CursorMoved(position) => {
+                w.write_all(&[6])?;
+                write_f64_pair(w, (position.x, position.y))
+            }
+            InputEvent::CursorEntered => w.write_all(&[7]),
+            InputEvent::CursorLeft => w.write_all(&[8]),
+            InputEvent::Focused(focused) => w.write_all(&[9, focused as u8]),
+            InputEvent::MouseMotion(delta) => {
+                w.write_all(&[10])?;
+                write_f64_pair(w, delta)
+            }
+            InputEvent::Gamepad(event) => match event {
+                GamepadEvent::Connected(id) => {
+                    w.write_all(&[11])?;
+                    write_varint(w, id as u64)
+                }
+                GamepadEvent::Disconnected(id) => {
+                    w.write_all(&[12])?;
+                    write_varint(w, id as u64)
+                }
+                GamepadEvent::ButtonPressed(id, button) => {
+                    w.write_all(&[13])?;
+                    write_varint(w, id as u64)?;
+                    w.write_all(&[button as u8])
+                }
+                GamepadEvent::ButtonReleased(id, button) => {
+                    w.write_all(&[14])?;
+                    write_varint(w, id as u64)?;
+                    w.write_all(&[button as u8])
+                }
+                GamepadEvent::AxisChanged(id, axis, value) => {
+                    w.write_all(&[15])?;
+                    write_varint(w, id as u64)?;
+                    w.write_all(&[axis as u8])?;
+                    w.write_all(&value.to_le_bytes())
+                }
+            },
+        }
+    }
+
+    fn read(tag: u8, r: &mut impl Read) -> io::Result<InputEvent> {
+        let event = match tag {
+            0 => InputEvent::Keyboard {
+                scancode: read_varint(r)? as u32,
+                state: read_state(r)?,
+                key: match read_varint(r)? {
+                    0 => None,
+                    code => Some(
+                        input::key_from_code((code - 1) as u32)
+                            .ok_or_else(|| invalid_data("invalid key code"))?,
+                    ),
+                },
+            },
+            1 => InputEvent::ReceivedCharacter(
+                std::char::from_u32(read_varint(r)? as u32)
+                    .ok_or_else(|| invalid_data("invalid character"))?,
+            ),
+            2 => InputEvent::ModifiersChanged(ModifiersState::from_bits_truncate(
+                read_varint(r)? as u32
+            )),
+            3 => InputEvent::MouseInput {
+                state: read_state(r)?,
+                button: match read_varint(r)? {
+                    0 => MouseButton::Left,
+                    1 => MouseButton::Right,
+                    2 => MouseButton::Middle,
+                    n => MouseButton::Other((n - 3) as u16),
+                },
+            },
+            4 => {
+                let mut bytes = [0; 4];
+                r.read_exact(&mut bytes)?;
+                let x = f32::from_le_bytes(bytes);
+                r.read_exact(&mut bytes)?;
+                let y = f32::from_le_bytes(bytes);
+                InputEvent::MouseWheel(MouseScrollDelta::LineDelta(x, y))
+            }
+            5 => {
+                let (x, y) = read_f64_pair(r)?;
+                InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(x, y)))
+            }
+            6 => {
+                let (x, y) = read_f64_pair(r)?;
+                InputEvent::CursorMoved(PhysicalPosition::new(x, y))
+            }
+            7 => InputEvent::CursorEntered,
+            8 => InputEvent::CursorLeft,
+            9 => InputEvent::Focused(read_u8(r)? != 0),
+            10 => InputEvent::MouseMotion(read_f64_pair(r)?),
+            11 => InputEvent::Gamepad(GamepadEvent::Connected(read_varint(r)? as usize)),
+            12 => InputEvent::Gamepad(GamepadEvent::Disconnected(read_varint(r)? as usize)),
+            13 => InputEvent::Gamepad(GamepadEvent::ButtonPressed(
+                read_varint(r)? as usize,
+                read_gamepad_button(r)?,
+            )),
+            14 => InputEvent::Gamepad(GamepadEvent::ButtonReleased(
+                read_varint(r)? as usize,
+                read_gamepad_button(r)?,
+            )),
+            15 => {
+                let id = read_varint(r)? as usize;
+                let axis = *gamepad::AXES
+                    .get(usize::from(read_u8(r)?))
+                    .ok_or_else(|| invalid_data("invalid gamepad axis"))?;
+                let mut bytes = [0; 4];
+                r.read_exact(&mut bytes)?;
+                InputEvent::Gamepad(GamepadEvent::AxisChanged(
+                    id,
+                    axis,
+                    f32::from_le_bytes(bytes),
+                ))
+            }
+            _ => return Err(invalid_data("invalid event tag")),
+        };
+        Ok(event)
+    }
+}
+
+// Recorder
+
+/// Records input events with the fixed step tick they are applied on.
+///
+/// File layout: a header (magic, version, update period, seed) followed by
+/// `(tick delta, event)` records, and an end record holding the last tick.
+/// The game loop flushes the recording every frame, so that it survives a crash
+/// (without the end record).
+pub struct Recorder {
+    writer: BufWriter<File>,
+    last_tick: u64,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        update_period: time::Duration,
+        seed: u64,
+    ) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        write_varint(&mut writer, update_period.as_nanos() as u64)?;
+        writer.write_all(&seed.to_le_bytes())?;
+        Ok(Recorder {
+            writer,
+            last_tick: 0,
+        })
+    }
+
+    pub fn record(&mut self, tick: u64, event: &InputEvent) -> io::Result<()> {
+        write_varint(&mut self.writer, tick - self.last_tick)?;
+        self.last_tick = tick;
+        event.write(&mut self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Writes the tick the recording ends on (so that trailing ticks without input
+    /// are replayed too) and flushes the recording.
+    pub fn finish(&mut self, tick: u64) -> io::Result<()> {
+        write_varint(&mut self.writer, tick.saturating_sub(self.last_tick))?;
+        self.last_tick = tick;
+        self.writer.write_all(&[END_TAG])?;
+        self.flush()
+    }
+}
+
+// Player
+
+/// Plays back a recording made with `Recorder`.
+pub struct Player {
+    update_period: time::Duration,
+    seed: u64,
+    events: VecDeque<(u64, InputEvent)>,
+    // tick the recording ended on (the last event tick for truncated recordings)
+    end_tick: u64,
+}
+
+impl Player {
+    /// Opens a recording. A truncated recording (e.g. the game crashed) is played
+    /// up to its last complete event.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Player> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    fn read(mut reader: impl Read) -> io::Result<Player> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a recording"));
+        }
+        if read_u8(&mut reader)? != VERSION {
+            return Err(invalid_data("unsupported recording version"));
+        }
+        let update_period = time::Duration::from_nanos(read_varint(&mut reader)?);
+        let mut seed = [0; 8];
+        reader.read_exact(&mut seed)?;
+        let seed = u64::from_le_bytes(seed);
+
+        let mut events = VecDeque::new();
+        let mut end_tick = 0;
+        loop {
+            match read_record(&mut reader, end_tick) {
+                Ok(Record::Event(tick, event)) => {
+                    events.push_back((tick, event));
+                    end_tick = tick;
+                }
+                Ok(Record::End(tick)) => {
+                    end_tick = tick;
+                    break;
+                }
+                // truncated recording, the partial record is dropped
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Player {
+            update_period,
+            seed,
+            events,
+            end_tick,
+        })
+    }
+
+    pub fn update_period(&self) -> time::Duration {
+        self.update_period
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the events to apply before running the given tick.
+    pub fn events(&mut self, tick: u64) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        while let Some((event_tick, event)) = self.events.front() {
+            if *event_tick > tick {
+                break;
+            }
+            events.push(*event);
+            self.events.pop_front();
+        }
+        events
+    }
+
+    /// Returns the tick the recording ended on.
+    pub fn end_tick(&self) -> u64 {
+        self.end_tick
+    }
+
+    /// Returns true once all the events were replayed and `tick` reached the end tick.
+    pub fn is_finished(&self, tick: u64) -> bool {
+        self.events.is_empty() && tick >= self.end_tick
+    }
+}
+
+// encoding
+
+enum Record {
+    Event(u64, InputEvent),
+    End(u64),
+}
+
+// reads the record following the one of `tick`
+fn read_record(r: &mut impl Read, tick: u64) -> io::Result<Record> {
+    let tick = tick + read_varint(r)?;
+    match read_u8(r)? {
+        END_TAG => Ok(Record::End(tick)),
+        tag => Ok(Record::Event(tick, InputEvent::read(tag, r)?)),
+    }
+}
+
+fn write_varint(w: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(r)?;
+        if shift >= 64 {
+            return Err(invalid_data("varint overflow"));
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    r.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn write_state(w: &mut impl Write, state: ElementState) -> io::Result<()> {
+    w.write_all(&[match state {
+        ElementState::Pressed => 1,
+        ElementState::Released => 0,
+    }])
+}
+
+fn read_state(r: &mut impl Read) -> io::Result<ElementState> {
+    Ok(match read_u8(r)? {
+        0 => ElementState::Released,
+        _ => ElementState::Pressed,
+    })
+}
+
+fn write_f64_pair(w: &mut impl Write, (x, y): (f64, f64)) -> io::Result<()> {
+    w.write_all(&x.to_le_bytes())?;
+    w.write_all(&y.to_le_bytes())
+}
+
+fn read_f64_pair(r: &mut impl Read) -> io::Result<(f64, f64)> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    let x = f64::from_le_bytes(bytes);
+    r.read_exact(&mut bytes)?;
+    let y = f64::from_le_bytes(bytes);
+    Ok((x, y))
+}
+
+fn read_gamepad_button(r: &mut impl Read) -> io::Result<gamepad::GamepadButton> {
+    gamepad::BUTTONS
+        .get(usize::from(read_u8(r)?))
+        .copied()
+        .ok_or_else(|| invalid_data("invalid gamepad button"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::{GamepadAxis, GamepadButton};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn events() -> Vec<InputEvent> {
+        vec![
+            InputEvent::Keyboard {
+                scancode: 30,
+                state: ElementState::Pressed,
+                key: Some(VirtualKeyCode::A),
+            },
+            InputEvent::Keyboard {
+                scancode: 1000,
+                state: ElementState::Released,
+                key: None,
+            },
+            InputEvent::ReceivedCharacter('é'),
+            InputEvent::ModifiersChanged(ModifiersState::CTRL | ModifiersState::SHIFT),
+            InputEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+            },
+            InputEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Other(300),
+            },
+            InputEvent::MouseWheel(MouseScrollDelta::LineDelta(-1.0, 2.5)),
+            InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(
+                0.25, -12.0,
+            ))),
+            InputEvent::CursorMoved(PhysicalPosition::new(640.5, 480.0)),
+            InputEvent::CursorEntered,
+            InputEvent::CursorLeft,
+            InputEvent::Focused(false),
+            InputEvent::MouseMotion((-3.0, 1e-3)),
+            InputEvent::Gamepad(GamepadEvent::Connected(2)),
+            InputEvent::Gamepad(GamepadEvent::ButtonPressed(2, GamepadButton::South)),
+            InputEvent::Gamepad(GamepadEvent::ButtonReleased(2, GamepadButton::Start)),
+            InputEvent::Gamepad(GamepadEvent::AxisChanged(2, GamepadAxis::DPadY, -0.75)),
+            InputEvent::Gamepad(GamepadEvent::Disconnected(2)),
+        ]
+    }
+
+    // records the events, one tick apart, ending at tick 100
+    fn record(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "winit_gameloop_{}_{}.rec",
+            name,
+            std::process::id()
+        ));
+        let update_period = time::Duration::from_millis(10);
+        let mut recorder = Recorder::create(&path, update_period, 42).unwrap();
+        for (tick, event) in events().iter().enumerate() {
+            recorder.record(tick as u64 * 2, event).unwrap();
+        }
+        recorder.finish(100).unwrap();
+        path
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        for event in events() {
+            let mut buffer = Vec::new();
+            event.write(&mut buffer).unwrap();
+            let mut reader = &buffer[1..];
+            assert_eq!(InputEvent::read(buffer[0], &mut reader).unwrap(), event);
+            assert!(reader.is_empty(), "{:?} not fully read", event);
+        }
+    }
+
+    #[test]
+    fn recording_round_trips_with_end_tick() {
+        let path = record("round_trip");
+        let mut player = Player::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(player.update_period(), time::Duration::from_millis(10));
+        assert_eq!(player.seed(), 42);
+        assert_eq!(player.end_tick(), 100);
+
+        let mut replayed = Vec::new();
+        for tick in 0..=100 {
+            let events = player.events(tick);
+            if tick % 2 == 1 {
+                assert!(events.is_empty());
+            }
+            replayed.extend(events);
+            // finished at the end tick, not at the last event
+            assert_eq!(player.is_finished(tick + 1), tick + 1 >= 100);
+        }
+        assert_eq!(replayed, events());
+    }
+
+    #[test]
+    fn truncated_recording_keeps_complete_events() {
+        let path = record("truncated");
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut period = Vec::new();
+        write_varint(&mut period, 10_000_000).unwrap();
+        let header_size = MAGIC.len() + 1 + period.len() + 8;
+        assert!(Player::read(&bytes[..header_size - 1]).is_err());
+
+        let all_events = events();
+        let mut last_count = 0;
+        for size in header_size..bytes.len() {
+            let mut player = Player::read(&bytes[..size]).unwrap();
+            let events = player.events(u64::MAX);
+            assert_eq!(events[..], all_events[..events.len()]);
+            assert!(events.len() >= last_count);
+            last_count = events.len();
+            // without the end record, the recording ends on the last event
+            let end_tick = events.len().saturating_sub(1) as u64 * 2;
+            assert_eq!(player.end_tick(), end_tick);
+        }
+        assert_eq!(last_count, all_events.len());
+    }
+}
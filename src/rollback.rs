@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+use std::time;
+
+use crate::game_loop::Tick;
+use crate::net::{InputExchange, NetInput, Transport};
+use crate::snapshot::Snapshot;
+
+// RollbackState
+
+/// The deterministic part of a game, simulated with the inputs of all players
+/// (see `RollbackSession`).
+pub trait RollbackState: Snapshot {
+    type Input: NetInput;
+
+    /// Runs a fixed step, `inputs` holds the input of each player (some may be predicted).
+    fn update_fixed_step(&mut self, inputs: &[Self::Input], tick: Tick, dt: time::Duration);
+}
+
+// RollbackStats
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RollbackStats {
+    /// Next tick to be simulated.
+    pub tick: u64,
+    /// Ticks for which the inputs of all players are known.
+    pub confirmed_tick: u64,
+    /// Number of rollbacks so far.
+    pub rollback_count: u64,
+    /// Number of ticks simulated again after rollbacks.
+    pub resimulated_ticks: u64,
+    /// Number of ticks rolled back by the last rollback.
+    pub last_rollback: u64,
+    /// Number of fixed steps skipped waiting for remote inputs.
+    pub stalled_steps: u64,
+}
+
+struct Frame<I> {
+    tick: u64,
+    // state at the beginning of the tick
+    snapshot: Vec<u8>,
+    // inputs the tick was simulated with
+    inputs: Vec<I>,
+}
+
+// RollbackSession
+
+/// Rollback networking on top of the fixed step.
+///
+/// Inputs are exchanged with the other players on each step; missing remote inputs are
+/// predicted (the last known input is repeated) so that the simulation never waits.
+/// When a remote input arrives and differs from the prediction, the state is rolled back
+/// to that tick and simulated again up to the current tick.
+///
+/// `advance` is called from `Game::update_fixed_step`. All players must use the same
+/// input delay and update period.
+pub struct RollbackSession<I: NetInput, T: Transport> {
+    transport: T,
+    exchange: InputExchange<I>,
+    input_delay: u64,
+    max_prediction: u64,
+    // next tick to be simulated
+    tick: u64,
+    // simulated ticks that may be rolled back
+    frames: VecDeque<Frame<I>>,
+    stats: RollbackStats,
+}
+
+impl<I: NetInput, T: Transport> RollbackSession<I, T> {
+    /// `input_delay` ticks are added to the local inputs,
+    /// which hides the latency of up to as many ticks without rollbacks.
+    pub fn new(
+        transport: T,
+        player_count: usize,
+        local_player: usize,
+        input_delay: u64,
+    ) -> RollbackSession<I, T> {
+        RollbackSession {
+            transport,
+            exchange: InputExchange::new(player_count, local_player, input_delay),
+            input_delay,
+            max_prediction: 8,
+            tick: 0,
+            frames: VecDeque::new(),
+            stats: RollbackStats::default(),
+        }
+    }
+
+    /// Sets how many ticks the simulation can run ahead of the confirmed inputs
+    /// (8 by default), beyond that steps are skipped until remote inputs arrive.
+    pub fn set_max_prediction(&mut self, max_prediction: u64) {
+        self.max_prediction = max_prediction;
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.exchange.local_player()
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.exchange.player_count()
+    }
+
+    /// Returns the next tick to be simulated.
+    pub fn tick(&self) -> Tick {
+        Tick(self.tick)
+    }
+
+    /// Returns the first tick for which the input of a player is missing.
+    pub fn confirmed_tick(&self) -> Tick {
+        Tick(self.exchange.confirmed())
+    }
+
+    pub fn stats(&self) -> RollbackStats {
+        self.stats
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Exchanges inputs, rolls back if needed, then simulates the next tick with the
+    /// local input (applied `input_delay` ticks later).
+    /// Returns false if the step was skipped, waiting for remote inputs.
+    pub fn advance<S>(&mut self, state: &mut S, local_input: I, dt: time::Duration) -> bool
+    where
+        S: RollbackState<Input = I>,
+    {
+        // check the predictions the ticks were simulated with
+        let received = self.exchange.receive(&mut self.transport);
+        let rollback_tick = received
+            .inputs
+            .iter()
+            .filter(|(player, tick, input)| {
+                self.frames
+                    .iter()
+                    .any(|frame| frame.tick == *tick && frame.inputs[*player] != *input)
+            })
+            .map(|(_, tick, _)| *tick)
+            .min();
+        if let Some(tick) = rollback_tick {
+            self.rollback(state, tick, dt);
+        }
+
+        if self.tick >= self.exchange.confirmed() + self.max_prediction {
+            // too far ahead of the other players
+            self.stats.stalled_steps += 1;
+            self.exchange.send(&mut self.transport, None);
+            return false;
+        }
+
+        self.exchange
+            .set_local_input(self.tick + self.input_delay, local_input);
+        self.exchange.send(&mut self.transport, None);
+
+        let inputs = self.exchange.predicted_inputs(self.tick);
+        let mut snapshot = Vec::new();
+        state.save(&mut snapshot);
+        state.update_fixed_step(&inputs, Tick(self.tick), dt);
+        self.frames.push_back(Frame {
+            tick: self.tick,
+            snapshot,
+            inputs,
+        });
+        self.tick += 1;
+
+        // confirmed ticks are never rolled back (the oldest frame is kept)
+        let confirmed = self.exchange.confirmed();
+        while self.frames.len() > 1 && self.frames[1].tick <= confirmed {
+            self.frames.pop_front();
+        }
+        let oldest = self.frames.front().map_or(self.tick, |frame| frame.tick);
+        self.exchange.prune(oldest);
+
+        self.stats.tick = self.tick;
+        self.stats.confirmed_tick = confirmed;
+        true
+    }
+
+    fn rollback<S>(&mut self, state: &mut S, tick: u64, dt: time::Duration)
+    where
+        S: RollbackState<Input = I>,
+    {
+        let index = match self.frames.iter().position(|frame| frame.tick == tick) {
+            Some(index) => index,
+            None => {
+                eprintln!("cannot roll back to tick {}", tick);
+                return;
+            }
+        };
+        state.restore(&self.frames[index].snapshot);
+        for (i, frame) in self.frames.iter_mut().skip(index).enumerate() {
+            let inputs = self.exchange.predicted_inputs(frame.tick);
+            // the first snapshot was just restored
+            if i > 0 {
+                frame.snapshot.clear();
+                state.save(&mut frame.snapshot);
+            }
+            state.update_fixed_step(&inputs, Tick(frame.tick), dt);
+            frame.inputs = inputs;
+        }
+        let length = self.tick - tick;
+        self.stats.rollback_count += 1;
+        self.stats.resimulated_ticks += length;
+        self.stats.last_rollback = length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::LoopbackTransport;
+    use std::thread;
+
+    // inputs change every tick until CHANGING_TICKS, then stay constant
+    const CHANGING_TICKS: u64 = 30;
+
+    #[derive(Default)]
+    struct State(u64);
+
+    impl Snapshot for State {
+        fn save(&self, buffer: &mut Vec<u8>) {
+            buffer.extend_from_slice(&self.0.to_le_bytes());
+        }
+
+        fn restore(&mut self, data: &[u8]) {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(data);
+            self.0 = u64::from_le_bytes(bytes);
+        }
+    }
+
+    impl RollbackState for State {
+        type Input = u8;
+
+        fn update_fixed_step(&mut self, inputs: &[u8], tick: Tick, _dt: time::Duration) {
+            for (player, input) in inputs.iter().enumerate() {
+                self.0 = self.0.wrapping_mul(31) ^ (*input as u64 * (player as u64 + 1));
+            }
+            self.0 = self.0.wrapping_add(tick.0);
+        }
+    }
+
+    fn input(player: usize, tick: Tick) -> u8 {
+        if tick.0 < CHANGING_TICKS {
+            ((tick.0 * 7 + player as u64 * 3) % 5) as u8
+        } else {
+            player as u8 + 1
+        }
+    }
+
+    fn snapshot(state: &State) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        state.save(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn mispredictions_roll_back_to_the_same_state() {
+        let dt = time::Duration::from_millis(16);
+        let latency = time::Duration::from_millis(5);
+        let mut players: Vec<_> = LoopbackTransport::network(2, latency)
+            .into_iter()
+            .enumerate()
+            .map(|(player, transport)| {
+                let session = RollbackSession::new(transport, 2, player, 0);
+                (session, State::default())
+            })
+            .collect();
+
+        // once the constant inputs are confirmed, the predictions are right
+        let synced = |players: &[(RollbackSession<u8, LoopbackTransport>, State)]| {
+            let tick = players[0].0.tick();
+            players.iter().all(|(session, _)| {
+                session.tick() == tick && session.confirmed_tick().0 > CHANGING_TICKS
+            })
+        };
+        let mut iterations = 0;
+        while !synced(&players) {
+            let tick = players.iter().map(|(session, _)| session.tick()).max();
+            for (player, (session, state)) in players.iter_mut().enumerate() {
+                // the leading players advance every other iteration, for the others to catch up
+                if Some(session.tick()) != tick || iterations % 2 == 0 {
+                    session.advance(state, input(player, session.tick()), dt);
+                }
+            }
+            thread::sleep(time::Duration::from_millis(1));
+            iterations += 1;
+            assert!(iterations < 10_000, "players did not sync");
+        }
+
+        let rollback_count: u64 = players
+            .iter()
+            .map(|(session, _)| session.stats().rollback_count)
+            .sum();
+        assert!(rollback_count > 0);
+        assert_eq!(snapshot(&players[0].1), snapshot(&players[1].1));
+    }
+}
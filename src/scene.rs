@@ -0,0 +1,558 @@
+use std::time;
+
+use winit::dpi::PhysicalSize;
+use winit::event::{Event, WindowEvent};
+
+use crate::game_loop::{Game, GameContext, GameStats, Tick};
+use crate::gamepad::GamepadEvent;
+
+/// What the scene stack should do after a scene update.
+pub enum Transition {
+    None,
+    /// Pushes a scene on top of the current one.
+    Push(Box<dyn Scene>),
+    /// Removes the current scene.
+    /// Popping the last scene leaves the stack empty: nothing is updated nor rendered
+    /// anymore, the game should exit (see `SceneStack::is_empty`).
+    Pop,
+    /// Replaces the current scene.
+    Replace(Box<dyn Scene>),
+}
+
+/// The progress, in [0.0, 1.0], of a transition between scenes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransitionPhase {
+    Entering(f32),
+    Leaving(f32),
+}
+
+// Scene
+
+/// A screen of the game (title, menu, gameplay, pause overlay...) managed by a `SceneStack`.
+///
+/// Only the top scene receives events and returns transitions,
+/// scenes below it are updated and rendered if they opt in.
+pub trait Scene {
+    fn event(&mut self, _event: &Event<'_, ()>) {}
+    fn gamepad(&mut self, _event: &GamepadEvent) {}
+    fn update_fixed_step(
+        &mut self,
+        context: &mut GameContext,
+        tick: Tick,
+        time: time::Duration,
+        dt: time::Duration,
+    ) -> Transition;
+    fn update(&mut self, _context: &mut GameContext, _time: time::Duration) -> Transition {
+        Transition::None
+    }
+    fn render(&mut self);
+    fn resized(&mut self, _size: PhysicalSize<u32>) {}
+    fn stats(&self, _game_stats: &GameStats) {}
+    /// Keeps updating when covered by another scene.
+    fn update_when_covered(&self) -> bool {
+        false
+    }
+    /// Keeps rendering when covered by another scene (i.e. under an overlay).
+    fn render_when_covered(&self) -> bool {
+        false
+    }
+    /// Number of fixed steps the transition lasts when this scene enters
+    /// (or leaves when popped), 0 for no transition.
+    fn transition_ticks(&self) -> u64 {
+        0
+    }
+    /// Called on each fixed step of a transition, instead of `update_fixed_step`.
+    fn transition(&mut self, _context: &mut GameContext, _phase: TransitionPhase) {}
+}
+
+enum Leaving {
+    // the scene below the pushed one
+    Covered,
+    // the popped or replaced scene, dropped at the end of the transition
+    Removed(Box<dyn Scene>),
+}
+
+struct ActiveTransition {
+    leaving: Option<Leaving>,
+    ticks: u64,
+    elapsed: u64,
+}
+
+// SceneStack
+
+/// A stack of scenes, implementing `Game`.
+///
+/// The game callbacks are routed to the scenes: the top scene gets events and updates,
+/// and the scenes below it get updates and renders if they opt in.
+/// During transitions scenes get `Scene::transition` calls instead of fixed updates,
+/// and both the entering and the leaving scenes are rendered.
+pub struct SceneStack {
+    window: Option<winit::window::Window>,
+    scenes: Vec<Box<dyn Scene>>,
+    transition: Option<ActiveTransition>,
+}
+
+impl SceneStack {
+    pub fn new(scene: Box<dyn Scene>) -> SceneStack {
+        let mut scene_stack = SceneStack {
+            window: None,
+            scenes: Vec::new(),
+            transition: None,
+        };
+        scene_stack.apply(Transition::Push(scene));
+        scene_stack
+    }
+
+    /// Sets the game window, redrawn when requested and used by the loop services.
+    pub fn set_window(&mut self, window: winit::window::Window) {
+        self.window = Some(window);
+    }
+
+    pub fn len(&self) -> usize {
+        self.scenes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub fn in_transition(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    pub fn push(&mut self, scene: Box<dyn Scene>) {
+        self.apply(Transition::Push(scene));
+    }
+
+    /// Removes the top scene (see `Transition::Pop`).
+    pub fn pop(&mut self) {
+        self.apply(Transition::Pop);
+    }
+
+    pub fn replace(&mut self, scene: Box<dyn Scene>) {
+        self.apply(Transition::Replace(scene));
+    }
+
+    fn apply(&mut self, transition: Transition) {
+        let (leaving, ticks) = match transition {
+            Transition::None => return,
+            Transition::Push(scene) => {
+                let ticks = scene.transition_ticks();
+                let leaving = if self.scenes.is_empty() {
+                    None
+                } else {
+                    Some(Leaving::Covered)
+                };
+                self.scenes.push(scene);
+                (leaving, ticks)
+            }
+            Transition::Pop => match self.scenes.pop() {
+                Some(scene) => {
+                    let ticks = scene.transition_ticks();
+                    (Some(Leaving::Removed(scene)), ticks)
+                }
+                None => return,
+            },
+            Transition::Replace(scene) => {
+                let ticks = scene.transition_ticks();
+                let leaving = self.scenes.pop().map(Leaving::Removed);
+                self.scenes.push(scene);
+                (leaving, ticks)
+            }
+        };
+        // a new transition ends the current one
+        self.transition = if ticks > 0 {
+            Some(ActiveTransition {
+                leaving,
+                ticks,
+                elapsed: 0,
+            })
+        } else {
+            None
+        };
+    }
+
+    fn top(&mut self) -> Option<&mut Box<dyn Scene>> {
+        self.scenes.last_mut()
+    }
+
+    // advances the transition by a fixed step
+    fn step_transition(&mut self, context: &mut GameContext) {
+        let transition = match &mut self.transition {
+            Some(transition) => transition,
+            None => return,
+        };
+        transition.elapsed += 1;
+        let progress = transition.elapsed as f32 / transition.ticks as f32;
+        let count = self.scenes.len();
+        match &mut transition.leaving {
+            Some(Leaving::Covered) if count >= 2 => {
+                self.scenes[count - 2].transition(context, TransitionPhase::Leaving(progress))
+            }
+            Some(Leaving::Removed(scene)) => {
+                scene.transition(context, TransitionPhase::Leaving(progress))
+            }
+            _ => {}
+        }
+        if let Some(scene) = self.scenes.last_mut() {
+            scene.transition(context, TransitionPhase::Entering(progress));
+        }
+        if transition.elapsed >= transition.ticks {
+            self.transition = None;
+        }
+    }
+
+    // the scenes below the top one that opted in
+    fn covered_scenes<F>(&mut self, filter: F) -> impl Iterator<Item = &mut Box<dyn Scene>>
+    where
+        F: Fn(&dyn Scene) -> bool,
+    {
+        let count = self.scenes.len().saturating_sub(1);
+        self.scenes[..count]
+            .iter_mut()
+            .filter(move |scene| filter(scene.as_ref()))
+    }
+}
+
+impl Game for SceneStack {
+    type RenderPacket = ();
+
+    fn init(&mut self) {}
+
+    fn start(&mut self) {}
+
+    fn event<T>(&mut self, event: &Event<'_, T>) {
+        if self.in_transition() {
+            return;
+        }
+        if let (Some(event), Some(scene)) = (unit_event(event), self.scenes.last_mut()) {
+            scene.event(&event);
+        }
+    }
+
+    fn gamepad(&mut self, event: &GamepadEvent) {
+        if self.in_transition() {
+            return;
+        }
+        if let Some(scene) = self.top() {
+            scene.gamepad(event);
+        }
+    }
+
+    fn update_fixed_step(
+        &mut self,
+        context: &mut GameContext,
+        tick: Tick,
+        time: time::Duration,
+        dt: time::Duration,
+    ) {
+        for scene in self.covered_scenes(|scene| scene.update_when_covered()) {
+            // only the top scene can change scenes
+            scene.update_fixed_step(context, tick, time, dt);
+        }
+        if self.in_transition() {
+            self.step_transition(context);
+            return;
+        }
+        if let Some(scene) = self.top() {
+            let transition = scene.update_fixed_step(context, tick, time, dt);
+            self.apply(transition);
+        }
+    }
+
+    fn update(&mut self, context: &mut GameContext, time: time::Duration) {
+        for scene in self.covered_scenes(|scene| scene.update_when_covered()) {
+            scene.update(context, time);
+        }
+        if self.in_transition() {
+            return;
+        }
+        if let Some(scene) = self.top() {
+            let transition = scene.update(context, time);
+            self.apply(transition);
+        }
+    }
+
+    fn render(&mut self) {
+        let covered_leaving = matches!(
+            self.transition,
+            Some(ActiveTransition {
+                leaving: Some(Leaving::Covered),
+                ..
+            })
+        );
+        let count = self.scenes.len();
+        for (index, scene) in self.scenes.iter_mut().enumerate() {
+            let top = index + 1 == count;
+            // the covered scene is visible until the pushed scene has entered
+            let leaving = covered_leaving && index + 2 == count;
+            if top || leaving || scene.render_when_covered() {
+                scene.render();
+            }
+        }
+        // the removed scene fades out on top
+        if let Some(ActiveTransition {
+            leaving: Some(Leaving::Removed(scene)),
+            ..
+        }) = &mut self.transition
+        {
+            scene.render();
+        }
+    }
+
+    fn resized(&mut self, size: PhysicalSize<u32>) {
+        for scene in &mut self.scenes {
+            scene.resized(size);
+        }
+        if let Some(ActiveTransition {
+            leaving: Some(Leaving::Removed(scene)),
+            ..
+        }) = &mut self.transition
+        {
+            scene.resized(size);
+        }
+    }
+
+    fn request_redraw(&self) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
+
+    fn window(&self) -> Option<&winit::window::Window> {
+        self.window.as_ref()
+    }
+
+    fn destroy(&self) {}
+
+    fn stats(&self, game_stats: &GameStats) {
+        for scene in &self.scenes {
+            scene.stats(game_stats);
+        }
+    }
+}
+
+/// Returns a copy of the event without user event, for the scenes
+/// (`None` for user events and scale factor changes, that hold a reference).
+fn unit_event<T>(event: &Event<'_, T>) -> Option<Event<'static, ()>> {
+    Some(match event {
+        Event::WindowEvent { window_id, event } => Event::WindowEvent {
+            window_id: *window_id,
+            event: window_event(event)?,
+        },
+        Event::DeviceEvent { device_id, event } => Event::DeviceEvent {
+            device_id: *device_id,
+            event: event.clone(),
+        },
+        Event::NewEvents(cause) => Event::NewEvents(*cause),
+        Event::MainEventsCleared => Event::MainEventsCleared,
+        Event::RedrawRequested(window_id) => Event::RedrawRequested(*window_id),
+        Event::RedrawEventsCleared => Event::RedrawEventsCleared,
+        Event::LoopDestroyed => Event::LoopDestroyed,
+        Event::Suspended => Event::Suspended,
+        Event::Resumed => Event::Resumed,
+        Event::UserEvent(_) => return None,
+    })
+}
+
+/// Returns a copy of the window event (`None` for scale factor changes, that hold a reference).
+fn window_event(event: &WindowEvent<'_>) -> Option<WindowEvent<'static>> {
+    if let WindowEvent::ScaleFactorChanged { .. } = event {
+        return None;
+    }
+    // SAFETY: the other events hold no reference, they are the ones `to_static` keeps
+    // and `WindowEvent<'static>::clone` copies
+    let event: &WindowEvent<'static> = unsafe { std::mem::transmute(event) };
+    Some(event.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::Executor;
+    use crate::jobs::JobSystem;
+    use crate::scheduler::Scheduler;
+    use crate::snapshot::SnapshotHistory;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use winit::event::{DeviceId, ElementState, KeyboardInput};
+    use winit::window::WindowId;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    struct Logged {
+        name: &'static str,
+        log: Log,
+        transition_ticks: u64,
+        // returned by the next fixed update
+        next: Option<Transition>,
+    }
+
+    impl Logged {
+        fn new(name: &'static str, log: &Log, transition_ticks: u64) -> Box<Logged> {
+            Box::new(Logged {
+                name,
+                log: log.clone(),
+                transition_ticks,
+                next: None,
+            })
+        }
+
+        fn then(mut self: Box<Self>, transition: Transition) -> Box<Self> {
+            self.next = Some(transition);
+            self
+        }
+
+        fn log(&self, message: String) {
+            self.log
+                .borrow_mut()
+                .push(format!("{} {}", self.name, message));
+        }
+    }
+
+    impl Scene for Logged {
+        fn update_fixed_step(
+            &mut self,
+            _context: &mut GameContext,
+            _tick: Tick,
+            _time: time::Duration,
+            _dt: time::Duration,
+        ) -> Transition {
+            self.log("update".to_string());
+            self.next.take().unwrap_or(Transition::None)
+        }
+
+        fn render(&mut self) {
+            self.log("render".to_string());
+        }
+
+        fn transition_ticks(&self) -> u64 {
+            self.transition_ticks
+        }
+
+        fn transition(&mut self, _context: &mut GameContext, phase: TransitionPhase) {
+            self.log(format!("{:?}", phase));
+        }
+    }
+
+    fn context() -> GameContext {
+        GameContext::new(
+            0,
+            Scheduler::new(),
+            JobSystem::single_threaded(),
+            Executor::new(None),
+            SnapshotHistory::disabled(),
+        )
+    }
+
+    // runs a fixed step and renders, returns what the scenes logged
+    fn frame(stack: &mut SceneStack, context: &mut GameContext, log: &Log) -> Vec<String> {
+        let dt = time::Duration::from_millis(10);
+        stack.update_fixed_step(context, Tick(0), time::Duration::from_secs(0), dt);
+        stack.render();
+        log.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn push_pop_replace() {
+        let log = Log::default();
+        let mut context = context();
+        let mut stack = SceneStack::new(Logged::new("a", &log, 0));
+        assert!(stack.window().is_none());
+        stack.push(Logged::new("b", &log, 0));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["b update", "b render"]
+        );
+        stack.replace(Logged::new("c", &log, 0));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["c update", "c render"]
+        );
+        stack.pop();
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["a update", "a render"]
+        );
+    }
+
+    #[test]
+    fn transitions_animate_both_scenes() {
+        let log = Log::default();
+        let mut context = context();
+        let b = Logged::new("b", &log, 2).then(Transition::Pop);
+        let mut stack = SceneStack::new(Logged::new("a", &log, 0).then(Transition::Push(b)));
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["a update", "a render", "b render"]
+        );
+        assert!(stack.in_transition());
+        // the covered scene leaves while the pushed one enters
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["a Leaving(0.5)", "b Entering(0.5)", "a render", "b render"]
+        );
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["a Leaving(1.0)", "b Entering(1.0)", "b render"]
+        );
+        assert!(!stack.in_transition());
+        // b pops itself and fades out on top of a
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["b update", "a render", "b render"]
+        );
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["b Leaving(0.5)", "a Entering(0.5)", "a render", "b render"]
+        );
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["b Leaving(1.0)", "a Entering(1.0)", "a render"]
+        );
+        assert_eq!(
+            frame(&mut stack, &mut context, &log),
+            ["a update", "a render"]
+        );
+    }
+
+    #[test]
+    fn popping_the_last_scene_empties_the_stack() {
+        let log = Log::default();
+        let mut context = context();
+        let mut stack = SceneStack::new(Logged::new("a", &log, 0).then(Transition::Pop));
+        assert_eq!(frame(&mut stack, &mut context, &log), ["a update"]);
+        assert!(stack.is_empty());
+        assert!(frame(&mut stack, &mut context, &log).is_empty());
+        stack.pop();
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn window_events_are_copied() {
+        let input = KeyboardInput {
+            scancode: 30,
+            state: ElementState::Pressed,
+            virtual_keycode: None,
+            modifiers: Default::default(),
+        };
+        let event = WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input,
+            is_synthetic: false,
+        };
+        assert_eq!(window_event(&event), Some(event.clone()));
+        let mut size = PhysicalSize::new(800, 600);
+        let event: Event<()> = Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: WindowEvent::ScaleFactorChanged {
+                scale_factor: 2.0,
+                new_inner_size: &mut size,
+            },
+        };
+        assert!(unit_event(&event).is_none());
+    }
+}
@@ -0,0 +1,272 @@
+use std::time;
+
+use crate::game_loop::GameStats;
+
+/// The loop stages at which scheduled tasks run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// At the beginning of the loop iteration, after `Game::start`.
+    NewEvents,
+    /// Before the fixed updates of the frame.
+    BeforeFixedUpdate,
+    /// After `Game::update`.
+    AfterUpdate,
+    /// After `Game::render`.
+    AfterRender,
+    /// At the end of the loop iteration, after `Game::stats`.
+    RedrawEventsCleared,
+}
+
+impl Stage {
+    /// NewEvents and RedrawEventsCleared run on every loop iteration,
+    /// the other stages only when a frame is updated or rendered.
+    fn always_runs(self) -> bool {
+        matches!(self, Stage::NewEvents | Stage::RedrawEventsCleared)
+    }
+}
+
+/// Identifies a scheduled task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskHandle(u64);
+
+/// What a task gets to see when it runs.
+///
+/// Tasks don't get the `GameContext`: the scheduler is part of it.
+/// They share state with the game through `Rc<Cell<_>>` (or similar),
+/// timer callbacks (see `TimerWheel::call_after_ticks`) do get the context.
+pub struct TaskContext<'a> {
+    pub stage: Stage,
+    pub now: time::Instant,
+    pub window: Option<&'a winit::window::Window>,
+    pub stats: &'a GameStats,
+}
+
+type Task = Box<dyn FnMut(&TaskContext<'_>)>;
+
+struct Entry {
+    handle: TaskHandle,
+    stage: Stage,
+    due: time::Instant,
+    interval: Option<time::Duration>,
+    task: Task,
+}
+
+// Scheduler
+
+/// Runs closures at given loop stages, once or at regular intervals.
+///
+/// The scheduler is owned by the game loop (see `GameLoop::scheduler`)
+/// and available to the game through `GameContext::scheduler`.
+/// Pending tasks wake the loop up when they are due, even when it is idle.
+#[derive(Default)]
+pub struct Scheduler {
+    entries: Vec<Entry>,
+    next_handle: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Self::default()
+    }
+
+    /// Runs a task the next time the stage is reached.
+    pub fn once<F>(&mut self, stage: Stage, task: F) -> TaskHandle
+    where
+        F: FnMut(&TaskContext<'_>) + 'static,
+    {
+        self.schedule(stage, time::Duration::from_secs(0), None, Box::new(task))
+    }
+
+    /// Runs a task the first time the stage is reached after `delay`.
+    pub fn once_after<F>(&mut self, stage: Stage, delay: time::Duration, task: F) -> TaskHandle
+    where
+        F: FnMut(&TaskContext<'_>) + 'static,
+    {
+        self.schedule(stage, delay, None, Box::new(task))
+    }
+
+    /// Runs a task at the given stage every `interval`, starting after `interval`.
+    pub fn every<F>(&mut self, stage: Stage, interval: time::Duration, task: F) -> TaskHandle
+    where
+        F: FnMut(&TaskContext<'_>) + 'static,
+    {
+        self.schedule(stage, interval, Some(interval), Box::new(task))
+    }
+
+    /// Cancels a task, returns false if it already ran (or was cancelled).
+    pub fn cancel(&mut self, handle: TaskHandle) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.handle != handle);
+        self.entries.len() != count
+    }
+
+    pub fn is_scheduled(&self, handle: TaskHandle) -> bool {
+        self.entries.iter().any(|entry| entry.handle == handle)
+    }
+
+    fn schedule(
+        &mut self,
+        stage: Stage,
+        delay: time::Duration,
+        interval: Option<time::Duration>,
+        task: Task,
+    ) -> TaskHandle {
+        let handle = TaskHandle(self.next_handle);
+        self.next_handle += 1;
+        self.entries.push(Entry {
+            handle,
+            stage,
+            due: time::Instant::now() + delay,
+            interval,
+            task,
+        });
+        handle
+    }
+
+    /// Runs the tasks due at this stage.
+    pub(crate) fn run(&mut self, context: &TaskContext<'_>) {
+        let now = context.now;
+        let mut index = 0;
+        while index < self.entries.len() {
+            let entry = &mut self.entries[index];
+            if entry.stage != context.stage || entry.due > now {
+                index += 1;
+                continue;
+            }
+            (entry.task)(context);
+            match entry.interval {
+                Some(interval) => {
+                    entry.due += interval;
+                    if entry.due <= now {
+                        // don't try to catch up with missed runs
+                        entry.due = now + interval;
+                    }
+                    index += 1;
+                }
+                None => {
+                    self.entries.remove(index);
+                }
+            }
+        }
+    }
+
+    /// Returns when the loop must wake up to run pending tasks.
+    ///
+    /// Tasks already due at a stage that only runs with frames
+    /// are left to the next frame.
+    pub(crate) fn wait_until(&self, now: time::Instant) -> Option<time::Instant> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                if entry.due > now {
+                    Some(entry.due)
+                } else if entry.stage.always_runs() {
+                    Some(now)
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const STAGES: [Stage; 5] = [
+        Stage::NewEvents,
+        Stage::BeforeFixedUpdate,
+        Stage::AfterUpdate,
+        Stage::AfterRender,
+        Stage::RedrawEventsCleared,
+    ];
+
+    fn run_stage(scheduler: &mut Scheduler, stage: Stage, now: time::Instant) {
+        scheduler.run(&TaskContext {
+            stage,
+            now,
+            window: None,
+            stats: &GameStats::default(),
+        });
+    }
+
+    // schedules a task logging its name (and stage) when run
+    fn logger(log: &Rc<RefCell<Vec<String>>>, name: &'static str) -> impl FnMut(&TaskContext<'_>) {
+        let log = log.clone();
+        move |context| {
+            log.borrow_mut()
+                .push(format!("{} {:?}", name, context.stage))
+        }
+    }
+
+    #[test]
+    fn tasks_run_at_their_stage_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        scheduler.once(Stage::AfterRender, logger(&log, "render"));
+        scheduler.once(Stage::NewEvents, logger(&log, "a"));
+        scheduler.once(Stage::NewEvents, logger(&log, "b"));
+        scheduler.once(Stage::AfterUpdate, logger(&log, "update"));
+
+        let now = time::Instant::now();
+        for &stage in STAGES.iter().rev() {
+            run_stage(&mut scheduler, stage, now);
+        }
+        assert_eq!(
+            *log.borrow(),
+            [
+                "render AfterRender",
+                "update AfterUpdate",
+                "a NewEvents",
+                "b NewEvents"
+            ]
+        );
+    }
+
+    #[test]
+    fn tasks_are_removed_once_run_or_cancelled() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        let once = scheduler.once(Stage::NewEvents, logger(&log, "once"));
+        let cancelled = scheduler.once(Stage::NewEvents, logger(&log, "cancelled"));
+        let second = time::Duration::from_secs(1);
+        let every = scheduler.every(Stage::NewEvents, second, logger(&log, "every"));
+        assert!(scheduler.cancel(cancelled));
+        assert!(!scheduler.cancel(cancelled));
+
+        let start = time::Instant::now();
+        run_stage(&mut scheduler, Stage::NewEvents, start);
+        assert!(!scheduler.is_scheduled(once));
+        assert!(!scheduler.cancel(once));
+        assert!(scheduler.is_scheduled(every));
+        // runs once however late, then every second again
+        run_stage(&mut scheduler, Stage::NewEvents, start + 5 * second);
+        run_stage(&mut scheduler, Stage::NewEvents, start + 5 * second);
+        run_stage(&mut scheduler, Stage::NewEvents, start + 6 * second);
+        assert!(scheduler.cancel(every));
+        run_stage(&mut scheduler, Stage::NewEvents, start + 10 * second);
+        assert_eq!(
+            *log.borrow(),
+            ["once NewEvents", "every NewEvents", "every NewEvents"]
+        );
+    }
+
+    #[test]
+    fn wait_until_leaves_frame_stages_to_frames() {
+        let mut scheduler = Scheduler::new();
+        let start = time::Instant::now();
+        let second = time::Duration::from_secs(1);
+        assert_eq!(scheduler.wait_until(start), None);
+        let render = scheduler.once(Stage::AfterRender, |_| {});
+        assert_eq!(scheduler.wait_until(start + second), None);
+        scheduler.cancel(render);
+        scheduler.once_after(Stage::AfterRender, 2 * second, |_| {});
+        let due = scheduler.wait_until(start + second).unwrap();
+        assert!(due > start + second && due <= time::Instant::now() + 2 * second);
+        scheduler.once(Stage::RedrawEventsCleared, |_| {});
+        assert_eq!(scheduler.wait_until(start + second), Some(start + second));
+    }
+}
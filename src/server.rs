@@ -0,0 +1,356 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time;
+
+use crate::executor;
+use crate::game_loop::{self, Game, GameContext, GameStats};
+use crate::jobs;
+use crate::scheduler::{self, Stage};
+use crate::snapshot;
+use crate::utility::shutdown;
+use crate::utility::timer;
+
+// the loop does not try to catch up when it is late by more ticks
+const MAX_LAG_TICKS: u32 = 8;
+// overruns are reported at most once per period
+const REPORT_PERIOD: time::Duration = time::Duration::from_secs(1);
+
+// ServerStats
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServerStats {
+    /// Number of ticks run.
+    pub tick_count: u64,
+    /// Time spent running the last tick.
+    pub tick_duration: time::Duration,
+    /// Longest tick so far.
+    pub max_tick_duration: time::Duration,
+    /// Number of ticks that started late, because the previous ones took too long.
+    pub overrun_count: u64,
+    /// Largest delay of a tick start.
+    pub max_overrun: time::Duration,
+    /// Number of ticks skipped after falling too far behind.
+    pub skipped_ticks: u64,
+}
+
+#[derive(Clone, Copy)]
+struct ServerSetup {
+    update_period: time::Duration,
+    job_threads: usize,
+    async_budget: time::Duration,
+}
+
+// ServerLoop
+
+/// Runs the fixed step part of a game at a precise tick rate, without window nor event loop
+/// (for dedicated servers sharing the gameplay code with the client).
+///
+/// Only `Game::init`, the fixed steps (with timers), `Game::destroy` and the async tasks are run.
+/// The scheduled tasks run once per tick, at every stage but `Stage::AfterRender`, and see
+/// the tick timings in `GameStats`. Snapshots are not supported (rewinds are ignored).
+/// The loop stops on SIGINT or SIGTERM, or when the shutdown flag is set.
+pub struct ServerLoop {
+    setup: ServerSetup,
+    seed: u64,
+    scheduler: scheduler::Scheduler,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ServerLoop {
+    pub fn new() -> Self {
+        let setup = ServerSetup {
+            update_period: time::Duration::from_secs_f32(1.0 / 60.0),
+            job_threads: 0,
+            async_budget: time::Duration::from_millis(2),
+        };
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        ServerLoop {
+            setup,
+            seed,
+            scheduler: scheduler::Scheduler::new(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets the fixed update period (1/60 s by default).
+    pub fn set_update_period(&mut self, update_period: time::Duration) {
+        assert!(
+            update_period > time::Duration::new(0, 0),
+            "invalid update period"
+        );
+        self.setup.update_period = update_period;
+    }
+
+    /// Sets the number of job worker threads (0 by default: jobs run on the loop thread).
+    pub fn set_job_threads(&mut self, thread_count: usize) {
+        self.setup.job_threads = thread_count;
+    }
+
+    /// Sets the time given to async tasks each tick (2 ms by default).
+    pub fn set_async_budget(&mut self, budget: time::Duration) {
+        self.setup.async_budget = budget;
+    }
+
+    /// Sets the seed returned by `GameContext::seed` (random by default).
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Returns the scheduler, to register tasks run each tick
+    /// (it is available to the game through `GameContext::scheduler` once running).
+    pub fn scheduler(&mut self) -> &mut scheduler::Scheduler {
+        &mut self.scheduler
+    }
+
+    /// Returns a flag that stops the loop, at the end of the current tick, once set.
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Runs the game until shutdown, then calls `Game::destroy` and returns the stats.
+    pub fn run<G: Game>(self, mut game: G) -> ServerStats {
+        if let Err(e) = shutdown::install_handler() {
+            eprintln!("failed to install shutdown handler ({})", e);
+        }
+        #[cfg(target_os = "windows")]
+        timer::set_timer_max_resolution();
+
+        let setup = self.setup;
+        let mut context = GameContext::new(
+            self.seed,
+            self.scheduler,
+            jobs::JobSystem::new(setup.job_threads),
+            executor::Executor::new(None),
+            snapshot::SnapshotHistory::disabled(),
+        );
+        let mut fired_timers = Vec::new();
+        let mut stats = ServerStats::default();
+        // seen by the scheduled tasks
+        let mut game_stats = GameStats::default();
+        game_stats.update_period = setup.update_period;
+        // overruns since the last report
+        let mut overruns = 0;
+        let mut last_report_time = time::Instant::now();
+
+        game.init();
+        let mut tick = 0;
+        let mut time = time::Duration::new(0, 0);
+        let mut next_tick_time = time::Instant::now();
+        while !shutdown::is_requested() && !self.shutdown.load(Ordering::SeqCst) {
+            let start_time = time::Instant::now();
+            for stage in [Stage::NewEvents, Stage::BeforeFixedUpdate] {
+                game_loop::run_tasks(context.scheduler(), stage, &game, &game_stats);
+            }
+            game_loop::fixed_step(
+                &mut game,
+                &mut context,
+                &mut fired_timers,
+                tick,
+                time,
+                setup.update_period,
+            );
+            context.executor().poll(true, setup.async_budget);
+            game_loop::run_tasks(context.scheduler(), Stage::AfterUpdate, &game, &game_stats);
+            if context.snapshots().take_request().is_some() {
+                eprintln!("rewind ignored, snapshots are not supported by the server loop");
+            }
+            tick += 1;
+            time += setup.update_period;
+
+            let now = time::Instant::now();
+            stats.tick_count = tick;
+            stats.tick_duration = now - start_time;
+            stats.max_tick_duration = stats.max_tick_duration.max(stats.tick_duration);
+            game_stats.frame_duration = stats.tick_duration;
+            game_stats.update = stats.tick_duration;
+            game_stats.jobs = context.jobs().take_stats();
+            game_loop::run_tasks(
+                context.scheduler(),
+                Stage::RedrawEventsCleared,
+                &game,
+                &game_stats,
+            );
+
+            next_tick_time += setup.update_period;
+            if now > next_tick_time {
+                next_tick_time = overrun(&mut stats, now, next_tick_time, setup.update_period);
+                overruns += 1;
+            } else {
+                timer::sleep_until(next_tick_time);
+            }
+
+            if overruns > 0 && now - last_report_time >= REPORT_PERIOD {
+                eprintln!(
+                    "{} tick overruns (tick {}, max overrun {:?}, max tick duration {:?})",
+                    overruns, tick, stats.max_overrun, stats.max_tick_duration
+                );
+                overruns = 0;
+                last_report_time = now;
+            }
+        }
+
+        game.destroy();
+        shutdown::complete();
+        #[cfg(target_os = "windows")]
+        timer::reset_timer_resolution();
+        stats
+    }
+}
+
+impl Default for ServerLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Accounts for a tick starting late, returns when the next tick starts.
+fn overrun(
+    stats: &mut ServerStats,
+    now: time::Instant,
+    next_tick_time: time::Instant,
+    update_period: time::Duration,
+) -> time::Instant {
+    let overrun = now - next_tick_time;
+    stats.overrun_count += 1;
+    stats.max_overrun = stats.max_overrun.max(overrun);
+    if overrun > update_period * MAX_LAG_TICKS {
+        // the simulation time does not follow the real time anymore
+        let skipped = (overrun.as_nanos() / update_period.as_nanos()) as u64;
+        stats.skipped_ticks += skipped;
+        now
+    } else {
+        next_tick_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_loop::{GameContext, Tick};
+    use crate::timers::TimerId;
+    use crate::utility::shutdown::tests::SHUTDOWN_STATE;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use winit::dpi::PhysicalSize;
+    use winit::event::Event;
+
+    #[derive(Default)]
+    struct Counts {
+        fixed_steps: Cell<u64>,
+        timers: Cell<u64>,
+        destroyed: Cell<bool>,
+    }
+
+    struct Counter {
+        counts: Rc<Counts>,
+        shutdown: Arc<AtomicBool>,
+        tick_count: u64,
+    }
+
+    impl Game for Counter {
+        type RenderPacket = ();
+
+        fn init(&mut self) {}
+        fn start(&mut self) {}
+        fn event<T>(&mut self, _event: &Event<'_, T>) {}
+
+        fn update_fixed_step(
+            &mut self,
+            context: &mut GameContext,
+            tick: Tick,
+            _time: time::Duration,
+            _dt: time::Duration,
+        ) {
+            if tick.0 == 0 {
+                context.timers().every_ticks(10);
+            }
+            let fixed_steps = &self.counts.fixed_steps;
+            fixed_steps.set(fixed_steps.get() + 1);
+            if tick.0 + 1 == self.tick_count {
+                self.shutdown.store(true, Ordering::SeqCst);
+            }
+        }
+
+        fn update(&mut self, _context: &mut GameContext, _time: time::Duration) {}
+        fn render(&mut self) {}
+        fn resized(&mut self, _size: PhysicalSize<u32>) {}
+
+        fn timer(&mut self, _context: &mut GameContext, _timer: TimerId) {
+            self.counts.timers.set(self.counts.timers.get() + 1);
+        }
+
+        fn request_redraw(&self) {}
+
+        fn destroy(&self) {
+            self.counts.destroyed.set(true);
+        }
+
+        fn stats(&self, _game_stats: &GameStats) {}
+    }
+
+    #[test]
+    fn runs_until_the_shutdown_flag_is_set() {
+        let _lock = SHUTDOWN_STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut server = ServerLoop::new();
+        server.set_update_period(time::Duration::from_millis(1));
+        let first_ticks = Rc::new(Cell::new(0));
+        let task_runs = Rc::new(Cell::new(0));
+        {
+            let first_ticks = first_ticks.clone();
+            server.scheduler().once(Stage::NewEvents, move |context| {
+                assert_eq!(context.stats.update_period, time::Duration::from_millis(1));
+                first_ticks.set(first_ticks.get() + 1);
+            });
+            let task_runs = task_runs.clone();
+            server
+                .scheduler()
+                .every(Stage::AfterUpdate, time::Duration::new(0, 0), move |_| {
+                    task_runs.set(task_runs.get() + 1)
+                });
+        }
+        let counts = Rc::new(Counts::default());
+        let game = Counter {
+            counts: counts.clone(),
+            shutdown: server.shutdown_flag(),
+            tick_count: 50,
+        };
+        let stats = server.run(game);
+
+        assert_eq!(stats.tick_count, 50);
+        assert_eq!(counts.fixed_steps.get(), 50);
+        // at ticks 10, 20, 30 and 40
+        assert_eq!(counts.timers.get(), 4);
+        assert_eq!(first_ticks.get(), 1);
+        assert_eq!(task_runs.get(), 50);
+        assert!(counts.destroyed.get());
+        assert!(stats.max_tick_duration >= stats.tick_duration);
+    }
+
+    #[test]
+    fn late_ticks_are_caught_up_or_skipped() {
+        let period = time::Duration::from_millis(10);
+        let start = time::Instant::now();
+        let mut stats = ServerStats::default();
+
+        // a few ticks late: the next ticks start right away to catch up
+        let next_tick_time = overrun(&mut stats, start + period * 3, start, period);
+        assert_eq!(next_tick_time, start);
+        assert_eq!(stats.overrun_count, 1);
+        assert_eq!(stats.max_overrun, period * 3);
+        assert_eq!(stats.skipped_ticks, 0);
+
+        // too late: the ticks are skipped
+        let now = start + period * (MAX_LAG_TICKS + 4) + period / 2;
+        assert_eq!(overrun(&mut stats, now, start, period), now);
+        assert_eq!(stats.overrun_count, 2);
+        assert_eq!(stats.max_overrun, now - start);
+        assert_eq!(stats.skipped_ticks, MAX_LAG_TICKS as u64 + 4);
+
+        overrun(&mut stats, start + period, start, period);
+        assert_eq!(stats.overrun_count, 3);
+        assert_eq!(stats.max_overrun, now - start);
+        assert_eq!(stats.skipped_ticks, MAX_LAG_TICKS as u64 + 4);
+    }
+}
@@ -0,0 +1,348 @@
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use winit::event::Event;
+use winit::window::WindowId;
+
+use crate::game_loop::Tick;
+use crate::gamepad::GamepadEvent;
+use crate::input::InputState;
+use crate::replay::InputEvent;
+use crate::utility::triple_buffer::{self, Reader, Writer};
+
+// the simulation does not try to catch up when it is late by more steps
+const MAX_LAG_STEPS: u32 = 8;
+
+// Simulation
+
+/// The fixed step part of a game, run on a dedicated thread (see `SimulationThread`).
+pub trait Simulation: Send + 'static {
+    /// The simulation state needed to render a frame.
+    type Snapshot: Clone + Send + 'static;
+
+    fn update_fixed_step(
+        &mut self,
+        input: &InputState,
+        tick: Tick,
+        time: time::Duration,
+        dt: time::Duration,
+    );
+    fn snapshot(&self) -> Self::Snapshot;
+}
+
+// Snapshots
+
+/// The latest two simulation snapshots, to interpolate between.
+#[derive(Clone, Debug)]
+pub struct Snapshots<T> {
+    pub previous: T,
+    pub current: T,
+    /// Tick of the fixed step that produced the current snapshot.
+    pub tick: Tick,
+    published: time::Instant,
+    dt: time::Duration,
+}
+
+impl<T> Snapshots<T> {
+    /// Returns the interpolation factor, in [0.0, 1.0], from the previous
+    /// to the current snapshot when rendering at `now`.
+    ///
+    /// Rendering is one fixed step behind the simulation: the previous snapshot
+    /// is shown when the current one is published, and the current one a step later.
+    pub fn alpha(&self, now: time::Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.published);
+        (elapsed.as_secs_f32() / self.dt.as_secs_f32()).min(1.0)
+    }
+}
+
+// SimulationSnapshots
+
+/// The snapshots published by a simulation thread, read without locking.
+pub struct SimulationSnapshots<T> {
+    reader: Reader<Snapshots<T>>,
+}
+
+impl<T> SimulationSnapshots<T> {
+    /// Returns true if new snapshots were published since the last call to `read`.
+    pub fn has_update(&self) -> bool {
+        self.reader.has_update()
+    }
+
+    /// Returns the latest two snapshots.
+    pub fn read(&mut self) -> &Snapshots<T> {
+        self.reader.read()
+    }
+}
+
+enum Message {
+    // starts a simulation spawned by the game loop, with the loop update period
+    Start(time::Duration),
+    Input(InputEvent),
+    Stop,
+}
+
+// the input side of a simulation thread run by the game loop (see `GameLoop::set_simulation`)
+pub(crate) trait SimulationControl {
+    fn start(&self, update_period: time::Duration);
+    fn forward(&self, input_event: InputEvent);
+    fn stop(&mut self);
+}
+
+struct Control<S: Simulation> {
+    sender: mpsc::Sender<Message>,
+    handle: Option<thread::JoinHandle<S>>,
+}
+
+impl<S: Simulation> Control<S> {
+    fn spawn(
+        simulation: S,
+        update_period: Option<time::Duration>,
+        initial_period: time::Duration,
+    ) -> io::Result<(Control<S>, SimulationSnapshots<S::Snapshot>)> {
+        let snapshot = simulation.snapshot();
+        let (writer, reader) = triple_buffer::triple_buffer(Snapshots {
+            previous: snapshot.clone(),
+            current: snapshot,
+            tick: Tick(0),
+            published: time::Instant::now(),
+            dt: initial_period,
+        });
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || run(simulation, update_period, receiver, writer))?;
+        let control = Control {
+            sender,
+            handle: Some(handle),
+        };
+        Ok((control, SimulationSnapshots { reader }))
+    }
+
+    fn send(&self, message: Message) {
+        // the simulation thread only stops when asked to (or on panic)
+        if self.sender.send(message).is_err() {
+            eprintln!("simulation thread is not running");
+        }
+    }
+
+    fn join(&mut self) -> Option<S> {
+        let handle = self.handle.take()?;
+        let _ = self.sender.send(Message::Stop);
+        handle.join().ok()
+    }
+}
+
+impl<S: Simulation> SimulationControl for Control<S> {
+    fn start(&self, update_period: time::Duration) {
+        self.send(Message::Start(update_period));
+    }
+
+    fn forward(&self, input_event: InputEvent) {
+        self.send(Message::Input(input_event));
+    }
+
+    fn stop(&mut self) {
+        self.join();
+    }
+}
+
+impl<S: Simulation> Drop for Control<S> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+pub(crate) type SimulationHandles<T> = (Box<dyn SimulationControl>, SimulationSnapshots<T>);
+
+/// Spawns a simulation thread that waits for the game loop to start it.
+pub(crate) fn spawn_stopped<S: Simulation>(
+    simulation: S,
+    update_period: time::Duration,
+) -> io::Result<SimulationHandles<S::Snapshot>> {
+    let (control, snapshots) = Control::spawn(simulation, None, update_period)?;
+    Ok((Box::new(control), snapshots))
+}
+
+// SimulationThread
+
+/// Runs a simulation at a fixed rate on a dedicated thread, so that heavy fixed steps
+/// don't delay event processing and rendering on the event loop thread.
+///
+/// Input events are forwarded from `Game::event` and `Game::gamepad`, and the latest
+/// two snapshots are read from `Game::render` without locking.
+/// `GameLoop::set_simulation` runs a simulation thread driven by the loop instead.
+pub struct SimulationThread<S: Simulation> {
+    control: Control<S>,
+    snapshots: SimulationSnapshots<S::Snapshot>,
+}
+
+impl<S: Simulation> SimulationThread<S> {
+    pub fn spawn(simulation: S, update_period: time::Duration) -> io::Result<SimulationThread<S>> {
+        let (control, snapshots) = Control::spawn(simulation, Some(update_period), update_period)?;
+        Ok(SimulationThread { control, snapshots })
+    }
+
+    /// Forwards an input event to the simulation (other events are ignored).
+    pub fn forward_event<T>(&self, event: &Event<'_, T>) {
+        if let Some(input_event) = InputEvent::from_event(event) {
+            self.control.forward(input_event);
+        }
+    }
+
+    pub fn forward_gamepad_event(&self, event: &GamepadEvent) {
+        self.control.forward(InputEvent::Gamepad(*event));
+    }
+
+    /// Returns true if new snapshots were published since the last call to `snapshots`.
+    pub fn has_update(&self) -> bool {
+        self.snapshots.has_update()
+    }
+
+    /// Returns the latest two snapshots.
+    pub fn snapshots(&mut self) -> &Snapshots<S::Snapshot> {
+        self.snapshots.read()
+    }
+
+    /// Stops the simulation and returns it (`None` if the simulation thread panicked).
+    pub fn stop(mut self) -> Option<S> {
+        self.control.join()
+    }
+}
+
+fn handle_input(input: &mut InputState, input_event: InputEvent, window_id: WindowId) {
+    match input_event {
+        InputEvent::Gamepad(event) => input.handle_gamepad_event(&event),
+        input_event => {
+            if let Some(event) = input_event.to_event::<()>(window_id) {
+                input.handle_event(&event);
+            }
+        }
+    }
+}
+
+// runs the fixed steps, once started (right away if `update_period` is given)
+fn run<S: Simulation>(
+    mut simulation: S,
+    update_period: Option<time::Duration>,
+    receiver: mpsc::Receiver<Message>,
+    mut writer: Writer<Snapshots<S::Snapshot>>,
+) -> S {
+    let window_id = unsafe { WindowId::dummy() };
+    let mut input = InputState::new();
+    let dt = match update_period {
+        Some(update_period) => update_period,
+        None => loop {
+            match receiver.recv() {
+                Ok(Message::Start(update_period)) => break update_period,
+                Ok(Message::Input(input_event)) => handle_input(&mut input, input_event, window_id),
+                Ok(Message::Stop) | Err(_) => return simulation,
+            }
+        },
+    };
+    let mut tick = 0;
+    let mut time = time::Duration::new(0, 0);
+    let mut previous = simulation.snapshot();
+    let mut next_step_time = time::Instant::now() + dt;
+    loop {
+        // handle input until the next step is due
+        loop {
+            let now = time::Instant::now();
+            let message = if now < next_step_time {
+                match receiver.recv_timeout(next_step_time - now) {
+                    Ok(message) => message,
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return simulation,
+                }
+            } else {
+                match receiver.try_recv() {
+                    Ok(message) => message,
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return simulation,
+                }
+            };
+            match message {
+                Message::Start(_) => {}
+                Message::Input(input_event) => handle_input(&mut input, input_event, window_id),
+                Message::Stop => return simulation,
+            }
+        }
+
+        // step
+        input.begin_step();
+        simulation.update_fixed_step(&input, Tick(tick), time, dt);
+        input.end_step();
+
+        // publish
+        let current = simulation.snapshot();
+        writer.write(Snapshots {
+            previous: std::mem::replace(&mut previous, current.clone()),
+            current,
+            tick: Tick(tick),
+            published: time::Instant::now(),
+            dt,
+        });
+        tick += 1;
+        time += dt;
+
+        next_step_time += dt;
+        let now = time::Instant::now();
+        if now > next_step_time + dt * MAX_LAG_STEPS {
+            next_step_time = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::GamepadButton;
+
+    // counts the steps and the steps with the south button down
+    struct Counter(u64, u64);
+
+    impl Simulation for Counter {
+        type Snapshot = (u64, u64);
+
+        fn update_fixed_step(
+            &mut self,
+            input: &InputState,
+            _tick: Tick,
+            _time: time::Duration,
+            _dt: time::Duration,
+        ) {
+            self.0 += 1;
+            if input.is_gamepad_button_down(0, GamepadButton::South) {
+                self.1 += 1;
+            }
+        }
+
+        fn snapshot(&self) -> (u64, u64) {
+            (self.0, self.1)
+        }
+    }
+
+    #[test]
+    fn loop_simulation_waits_for_start() {
+        let dt = time::Duration::from_millis(1);
+        let (mut control, mut snapshots) = spawn_stopped(Counter(0, 0), dt).unwrap();
+        control.forward(InputEvent::Gamepad(GamepadEvent::ButtonPressed(
+            0,
+            GamepadButton::South,
+        )));
+        thread::sleep(dt * 20);
+        assert!(!snapshots.has_update());
+
+        control.start(dt);
+        let deadline = time::Instant::now() + time::Duration::from_secs(10);
+        while snapshots.read().tick.0 < 5 {
+            assert!(time::Instant::now() < deadline, "simulation did not run");
+            thread::yield_now();
+        }
+        let (steps, steps_down) = snapshots.read().current;
+        assert!(steps >= 5);
+        assert_eq!(steps, steps_down);
+        control.stop();
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time;
+
+use crate::game_loop::Tick;
+use crate::input::InputState;
+use crate::replay::InputEvent;
+
+// Snapshot
+
+/// Game state that can be saved and restored, to rewind the game
+/// (see `Game::snapshot` and `GameContext::snapshots`).
+pub trait Snapshot {
+    /// Appends the game state to `buffer`.
+    fn save(&self, buffer: &mut Vec<u8>);
+    /// Restores the game state saved by `save`.
+    fn restore(&mut self, data: &[u8]);
+}
+
+struct Entry {
+    tick: u64,
+    time: time::Duration,
+    data: Vec<u8>,
+    // input state at the beginning of the tick
+    input: InputState,
+    // raw cursor motion accumulated before the tick
+    cursor_delta: (f64, f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Request {
+    Rewind(u64),
+    Resimulate(u64),
+}
+
+/// A snapshot to restore, with the input state it was captured with.
+pub(crate) struct RestorePoint<'a> {
+    pub(crate) tick: u64,
+    pub(crate) time: time::Duration,
+    pub(crate) data: &'a [u8],
+    pub(crate) input: &'a InputState,
+    pub(crate) cursor_delta: (f64, f64),
+}
+
+// SnapshotHistory
+
+/// Snapshots of the game state, captured every N fixed steps into a ring buffer,
+/// with the input events received since the oldest one.
+///
+/// A rewind restores the game to an earlier tick and continues from there,
+/// a resimulation restores the game to an earlier tick and runs the fixed steps up to the
+/// current tick again, with the same input (to investigate a bug, e.g. with a breakpoint).
+/// The game doesn't get the input events again, only `GameContext::input` replays them.
+/// Timers and async tasks are not rewound, they don't fire during resimulations.
+pub struct SnapshotHistory {
+    // 0 when disabled
+    interval: u64,
+    capacity: usize,
+    entries: VecDeque<Entry>,
+    events: VecDeque<(u64, InputEvent)>,
+    request: Option<Request>,
+    memory_usage: usize,
+}
+
+impl SnapshotHistory {
+    pub(crate) fn new(interval: u64, capacity: usize) -> SnapshotHistory {
+        SnapshotHistory {
+            interval,
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            events: VecDeque::new(),
+            request: None,
+            memory_usage: 0,
+        }
+    }
+
+    pub(crate) fn disabled() -> SnapshotHistory {
+        Self::new(0, 0)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval > 0 && self.capacity > 0
+    }
+
+    /// Number of snapshots in the ring buffer.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the earliest tick the game can be rewound to.
+    pub fn oldest_tick(&self) -> Option<Tick> {
+        self.entries.front().map(|entry| Tick(entry.tick))
+    }
+
+    /// Returns the memory used by the snapshots and the recorded input events, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage + self.events.len() * mem::size_of::<(u64, InputEvent)>()
+    }
+
+    /// Rewinds the game to the latest snapshot at or before `tick`,
+    /// before the next frame update.
+    pub fn rewind(&mut self, tick: Tick) {
+        self.request = Some(Request::Rewind(tick.0));
+    }
+
+    /// Restores the game to the latest snapshot at or before `tick` and runs the fixed
+    /// steps up to the current tick again, before the next frame update.
+    pub fn resimulate(&mut self, tick: Tick) {
+        self.request = Some(Request::Resimulate(tick.0));
+    }
+
+    pub(crate) fn take_request(&mut self) -> Option<Request> {
+        self.request.take()
+    }
+
+    /// Returns true if a snapshot must be captured at the beginning of this tick.
+    pub(crate) fn is_due(&self, tick: u64) -> bool {
+        self.is_enabled() && tick.is_multiple_of(self.interval)
+    }
+
+    pub(crate) fn capture(
+        &mut self,
+        tick: u64,
+        time: time::Duration,
+        snapshot: &dyn Snapshot,
+        input: &InputState,
+        cursor_delta: (f64, f64),
+    ) {
+        // snapshots of resimulated or rewound ticks are replaced
+        while matches!(self.entries.back(), Some(entry) if entry.tick >= tick) {
+            self.pop_back();
+        }
+        // reuse the oldest buffer
+        let mut data = if self.entries.len() >= self.capacity {
+            self.pop_front().map(|entry| entry.data).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        data.clear();
+        snapshot.save(&mut data);
+        self.memory_usage += data.len();
+        self.entries.push_back(Entry {
+            tick,
+            time,
+            data,
+            input: input.clone(),
+            cursor_delta,
+        });
+        // events before the oldest snapshot can't be replayed anymore
+        if let Some(oldest) = self.entries.front() {
+            while matches!(self.events.front(), Some(&(event_tick, _)) if event_tick <= oldest.tick)
+            {
+                self.events.pop_front();
+            }
+        }
+    }
+
+    /// Records an input event applied on `tick`.
+    pub(crate) fn record(&mut self, tick: u64, event: &InputEvent) {
+        if !self.entries.is_empty() {
+            self.events.push_back((tick, *event));
+        }
+    }
+
+    /// Returns the latest snapshot at or before `tick`.
+    pub(crate) fn restore_point(&self, tick: u64) -> Option<RestorePoint<'_>> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.tick <= tick)
+            .map(|entry| RestorePoint {
+                tick: entry.tick,
+                time: entry.time,
+                data: &entry.data,
+                input: &entry.input,
+                cursor_delta: entry.cursor_delta,
+            })
+    }
+
+    /// Returns the input events recorded for `tick`.
+    pub(crate) fn events(&self, tick: u64) -> Vec<InputEvent> {
+        self.events
+            .iter()
+            .filter(|(event_tick, _)| *event_tick == tick)
+            .map(|(_, event)| *event)
+            .collect()
+    }
+
+    /// Forgets the snapshots and input events after `tick` (the game was rewound to it).
+    pub(crate) fn truncate(&mut self, tick: u64) {
+        while matches!(self.entries.back(), Some(entry) if entry.tick > tick) {
+            self.pop_back();
+        }
+        while matches!(self.events.back(), Some(&(event_tick, _)) if event_tick > tick) {
+            self.events.pop_back();
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<Entry> {
+        let entry = self.entries.pop_front()?;
+        self.memory_usage -= entry.data.len();
+        Some(entry)
+    }
+
+    fn pop_back(&mut self) -> Option<Entry> {
+        let entry = self.entries.pop_back()?;
+        self.memory_usage -= entry.data.len();
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Bytes(Vec<u8>);
+
+    impl Snapshot for Bytes {
+        fn save(&self, buffer: &mut Vec<u8>) {
+            buffer.extend_from_slice(&self.0);
+        }
+
+        fn restore(&mut self, data: &[u8]) {
+            self.0 = data.to_vec();
+        }
+    }
+
+    fn capture(history: &mut SnapshotHistory, tick: u64, size: usize) {
+        let time = time::Duration::from_millis(tick * 10);
+        let state = Bytes(vec![tick as u8; size]);
+        history.capture(tick, time, &state, &InputState::new(), (0.0, 0.0));
+    }
+
+    fn event_ticks(history: &SnapshotHistory) -> Vec<u64> {
+        history.events.iter().map(|(tick, _)| *tick).collect()
+    }
+
+    #[test]
+    fn snapshots_are_due_every_interval() {
+        let history = SnapshotHistory::new(4, 3);
+        assert!(history.is_enabled());
+        let due: Vec<_> = (0..13).filter(|&tick| history.is_due(tick)).collect();
+        assert_eq!(due, [0, 4, 8, 12]);
+        assert!(!SnapshotHistory::disabled().is_due(0));
+        assert!(!SnapshotHistory::new(4, 0).is_due(4));
+    }
+
+    #[test]
+    fn oldest_snapshots_are_evicted_with_their_events() {
+        let mut history = SnapshotHistory::new(4, 3);
+        // not recorded before the first snapshot
+        history.record(0, &InputEvent::CursorEntered);
+        for tick in 0..13 {
+            if history.is_due(tick) {
+                capture(&mut history, tick, 10);
+            }
+            history.record(tick, &InputEvent::Focused(true));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.oldest_tick(), Some(Tick(4)));
+        // events up to the oldest snapshot tick can't be replayed
+        assert_eq!(event_ticks(&history), (5..13).collect::<Vec<_>>());
+        assert_eq!(history.events(6), [InputEvent::Focused(true)]);
+
+        let restore_point = history.restore_point(11).unwrap();
+        assert_eq!(restore_point.tick, 8);
+        assert_eq!(restore_point.time, time::Duration::from_millis(80));
+        assert_eq!(restore_point.data, &[8; 10]);
+        assert!(history.restore_point(3).is_none());
+    }
+
+    #[test]
+    fn truncate_forgets_the_future() {
+        let mut history = SnapshotHistory::new(4, 8);
+        for tick in 0..13 {
+            if history.is_due(tick) {
+                capture(&mut history, tick, 10);
+            }
+            history.record(tick, &InputEvent::CursorLeft);
+        }
+        history.truncate(5);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.restore_point(12).unwrap().tick, 4);
+        // the events of tick 0 are part of its snapshot
+        assert_eq!(event_ticks(&history), (1..6).collect::<Vec<_>>());
+        // resimulated ticks replace their snapshots
+        capture(&mut history, 4, 20);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.restore_point(4).unwrap().data, &[4; 20]);
+    }
+
+    #[test]
+    fn memory_usage_counts_snapshots_and_events() {
+        let event_size = mem::size_of::<(u64, InputEvent)>();
+        let mut history = SnapshotHistory::new(1, 2);
+        assert_eq!(history.memory_usage(), 0);
+        capture(&mut history, 0, 100);
+        capture(&mut history, 1, 50);
+        history.record(1, &InputEvent::CursorEntered);
+        history.record(1, &InputEvent::CursorLeft);
+        assert_eq!(history.memory_usage(), 150 + 2 * event_size);
+        // evicts the snapshot of tick 0, and the events up to tick 1
+        capture(&mut history, 2, 30);
+        assert_eq!(history.memory_usage(), 80);
+        history.truncate(1);
+        assert_eq!(history.memory_usage(), 50);
+    }
+}
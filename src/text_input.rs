@@ -0,0 +1,205 @@
+use winit::dpi::Position;
+use winit::event::{Event, Ime, WindowEvent};
+use winit::window::Window;
+
+use crate::clipboard::{Clipboard, MemoryClipboard};
+
+// TextInput
+
+/// Text input service, for chat and console widgets.
+///
+/// While enabled, characters and IME commits are accumulated and can be read
+/// with `text` during the frame's updates; the IME composition in progress
+/// is available with `preedit`.
+/// IME requests are applied to `Game::window` at the end of the frame updates.
+pub struct TextInput {
+    enabled: bool,
+    enabled_changed: bool,
+    ime_position: Option<Position>,
+    // text committed during the frame
+    text: String,
+    // composition in progress, with the cursor range (in bytes)
+    preedit: Option<(String, Option<(usize, usize)>)>,
+    clipboard: Box<dyn Clipboard>,
+}
+
+impl Default for TextInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextInput {
+    pub fn new() -> TextInput {
+        TextInput {
+            enabled: false,
+            enabled_changed: false,
+            ime_position: None,
+            text: String::new(),
+            preedit: None,
+            clipboard: Box::new(MemoryClipboard::new()),
+        }
+    }
+
+    /// Starts accepting text and allows the IME.
+    pub fn enable(&mut self) {
+        self.enabled_changed |= !self.enabled;
+        self.enabled = true;
+    }
+
+    /// Stops accepting text and disallows the IME.
+    pub fn disable(&mut self) {
+        self.enabled_changed |= self.enabled;
+        self.enabled = false;
+        self.preedit = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sets the position of the IME candidate window (i.e. the text cursor position).
+    pub fn set_candidate_position<P: Into<Position>>(&mut self, position: P) {
+        self.ime_position = Some(position.into());
+    }
+
+    /// Returns the text committed during the frame.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the IME composition in progress and its cursor range (in bytes), if any.
+    pub fn preedit(&self) -> Option<(&str, Option<(usize, usize)>)> {
+        self.preedit
+            .as_ref()
+            .map(|(text, cursor)| (text.as_str(), *cursor))
+    }
+
+    pub fn clipboard(&mut self) -> &mut dyn Clipboard {
+        self.clipboard.as_mut()
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) {
+        self.clipboard = clipboard;
+    }
+
+    pub(crate) fn handle_event<T>(&mut self, event: &Event<'_, T>) {
+        if !self.enabled {
+            return;
+        }
+        if let Event::WindowEvent { event, .. } = event {
+            match event {
+                WindowEvent::ReceivedCharacter(c) if !c.is_control() => self.text.push(*c),
+                WindowEvent::Ime(Ime::Preedit(text, cursor)) => {
+                    self.preedit = if text.is_empty() {
+                        None
+                    } else {
+                        Some((text.clone(), *cursor))
+                    };
+                }
+                WindowEvent::Ime(Ime::Commit(text)) => {
+                    self.preedit = None;
+                    self.text.push_str(text);
+                }
+                WindowEvent::Ime(Ime::Disabled) => self.preedit = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Applies IME requests to the window.
+    pub(crate) fn apply(&mut self, window: &Window) {
+        if self.enabled_changed {
+            window.set_ime_allowed(self.enabled);
+            self.enabled_changed = false;
+        }
+        if let Some(position) = self.ime_position.take() {
+            window.set_ime_position(position);
+        }
+    }
+
+    /// Replaces the text committed during the frame, returns the previous one.
+    pub(crate) fn replace_text(&mut self, text: String) -> String {
+        std::mem::replace(&mut self.text, text)
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.text.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use winit::window::WindowId;
+
+    fn window_event(event: WindowEvent<'static>) -> Event<'static, ()> {
+        Event::WindowEvent {
+            // only the event matters to the text input
+            window_id: unsafe { WindowId::dummy() },
+            event,
+        }
+    }
+
+    fn type_text(text_input: &mut TextInput, text: &str) {
+        for c in text.chars() {
+            text_input.handle_event(&window_event(WindowEvent::ReceivedCharacter(c)));
+        }
+    }
+
+    // a memory clipboard shared with the test, as a system clipboard would be
+    struct SharedClipboard(Rc<RefCell<MemoryClipboard>>);
+
+    impl Clipboard for SharedClipboard {
+        fn get(&mut self) -> Option<String> {
+            self.0.borrow_mut().get()
+        }
+
+        fn set(&mut self, text: &str) {
+            self.0.borrow_mut().set(text)
+        }
+    }
+
+    #[test]
+    fn copy_paste_round_trip() {
+        let mut text_input = TextInput::new();
+        assert_eq!(text_input.clipboard().get(), None);
+
+        text_input.enable();
+        type_text(&mut text_input, "héllo\u{8}");
+        text_input.handle_event(&window_event(WindowEvent::Ime(Ime::Commit(
+            " 世界".to_string(),
+        ))));
+        let copied = text_input.text().to_string();
+        assert_eq!(copied, "héllo 世界");
+        text_input.clipboard().set(&copied);
+        text_input.end_frame();
+        assert_eq!(text_input.text(), "");
+
+        // the clipboard outlives the frame
+        assert_eq!(text_input.clipboard().get().as_deref(), Some("héllo 世界"));
+        text_input.clipboard().set("replaced");
+        assert_eq!(text_input.clipboard().get().as_deref(), Some("replaced"));
+    }
+
+    #[test]
+    fn set_clipboard_replaces_the_default() {
+        let shared = Rc::new(RefCell::new(MemoryClipboard::new()));
+        shared.borrow_mut().set("from outside");
+
+        let mut text_input = TextInput::new();
+        text_input.set_clipboard(Box::new(SharedClipboard(shared.clone())));
+        assert_eq!(
+            text_input.clipboard().get().as_deref(),
+            Some("from outside")
+        );
+
+        text_input.enable();
+        type_text(&mut text_input, "copied");
+        let text = text_input.text().to_string();
+        text_input.clipboard().set(&text);
+        assert_eq!(shared.borrow_mut().get().as_deref(), Some("copied"));
+    }
+}
@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time;
+
+use crate::game_loop::{GameContext, Tick};
+
+// number of slots of the wheel (timers further away wait for several turns)
+const SLOT_COUNT: usize = 256;
+
+/// Identifies a scheduled timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// A callback run when a timer fires.
+pub type TimerCallback = Rc<RefCell<dyn FnMut(&mut GameContext, TimerId)>>;
+
+/// A timer that fired, either reported to `Game::timer` or calling back.
+pub(crate) struct Fired {
+    pub(crate) id: TimerId,
+    pub(crate) callback: Option<TimerCallback>,
+}
+
+struct Entry {
+    id: TimerId,
+    // wheel turns left before the timer is due
+    rounds: u64,
+    interval: Option<u64>,
+    callback: Option<TimerCallback>,
+}
+
+// TimerWheel
+
+/// Schedules timers in ticks (fixed steps) or at a wall-clock time.
+///
+/// Timers fire at the beginning of fixed steps, before `Game::update_fixed_step`:
+/// they are reported to `Game::timer` or call the given callback.
+/// Tick timers are deterministic (and replay well), wall-clock timers are not.
+pub struct TimerWheel {
+    slots: Vec<Vec<Entry>>,
+    // wall-clock timers, sorted by due time
+    clock_timers: Vec<(time::Instant, TimerId, Option<TimerCallback>)>,
+    // next tick to be run
+    next_tick: u64,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    pub(crate) fn new() -> TimerWheel {
+        TimerWheel {
+            slots: (0..SLOT_COUNT).map(|_| Vec::new()).collect(),
+            clock_timers: Vec::new(),
+            next_tick: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Fires once, `n` ticks after the current tick (at the next tick if `n` is 0).
+    pub fn after_ticks(&mut self, n: u64) -> TimerId {
+        self.schedule(n, None, None)
+    }
+
+    /// Fires every `n` ticks, starting `n` ticks after the current tick.
+    pub fn every_ticks(&mut self, n: u64) -> TimerId {
+        assert!(n > 0, "timer interval must not be zero");
+        self.schedule(n, Some(n), None)
+    }
+
+    /// Fires once, at the first tick run at or after `instant`.
+    pub fn at(&mut self, instant: time::Instant) -> TimerId {
+        self.schedule_at(instant, None)
+    }
+
+    /// Like `after_ticks`, calling `callback` instead of `Game::timer`.
+    pub fn call_after_ticks<F>(&mut self, n: u64, callback: F) -> TimerId
+    where
+        F: FnMut(&mut GameContext, TimerId) + 'static,
+    {
+        self.schedule(n, None, Some(Rc::new(RefCell::new(callback))))
+    }
+
+    /// Like `every_ticks`, calling `callback` instead of `Game::timer`.
+    pub fn call_every_ticks<F>(&mut self, n: u64, callback: F) -> TimerId
+    where
+        F: FnMut(&mut GameContext, TimerId) + 'static,
+    {
+        assert!(n > 0, "timer interval must not be zero");
+        self.schedule(n, Some(n), Some(Rc::new(RefCell::new(callback))))
+    }
+
+    /// Like `at`, calling `callback` instead of `Game::timer`.
+    pub fn call_at<F>(&mut self, instant: time::Instant, callback: F) -> TimerId
+    where
+        F: FnMut(&mut GameContext, TimerId) + 'static,
+    {
+        self.schedule_at(instant, Some(Rc::new(RefCell::new(callback))))
+    }
+
+    /// Cancels a timer, returns false if it already fired (or was cancelled).
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        for slot in &mut self.slots {
+            if let Some(index) = slot.iter().position(|entry| entry.id == id) {
+                slot.swap_remove(index);
+                return true;
+            }
+        }
+        if let Some(index) = self.clock_timers.iter().position(|(_, i, _)| *i == id) {
+            self.clock_timers.remove(index);
+            return true;
+        }
+        false
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.slots.iter().map(Vec::len).sum::<usize>() + self.clock_timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn next_id(&mut self) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn schedule(
+        &mut self,
+        n: u64,
+        interval: Option<u64>,
+        callback: Option<TimerCallback>,
+    ) -> TimerId {
+        let id = self.next_id();
+        // the current tick is the one being run (or the last one run)
+        let due = self.next_tick + n.max(1) - 1;
+        self.insert(
+            due,
+            Entry {
+                id,
+                rounds: 0,
+                interval,
+                callback,
+            },
+        );
+        id
+    }
+
+    fn schedule_at(&mut self, instant: time::Instant, callback: Option<TimerCallback>) -> TimerId {
+        let id = self.next_id();
+        let index = self
+            .clock_timers
+            .partition_point(|(other, _, _)| *other <= instant);
+        self.clock_timers.insert(index, (instant, id, callback));
+        id
+    }
+
+    fn insert(&mut self, due: u64, mut entry: Entry) {
+        entry.rounds = (due - self.next_tick) / SLOT_COUNT as u64;
+        self.slots[due as usize % SLOT_COUNT].push(entry);
+    }
+
+    /// Moves the wheel to `tick` (the game was rewound),
+    /// pending timers keep their remaining delay.
+    pub(crate) fn rebase(&mut self, tick: u64) {
+        let slots = std::mem::replace(
+            &mut self.slots,
+            (0..SLOT_COUNT).map(|_| Vec::new()).collect(),
+        );
+        let first_slot = self.next_tick as usize % SLOT_COUNT;
+        self.next_tick = tick;
+        for (index, slot) in slots.into_iter().enumerate() {
+            let offset = ((index + SLOT_COUNT - first_slot) % SLOT_COUNT) as u64;
+            for entry in slot {
+                let delay = offset + entry.rounds * SLOT_COUNT as u64;
+                self.insert(tick + delay, entry);
+            }
+        }
+    }
+
+    /// Runs a tick, collecting the timers due.
+    pub(crate) fn advance(&mut self, tick: Tick, now: time::Instant, fired: &mut Vec<Fired>) {
+        debug_assert_eq!(tick.0, self.next_tick, "ticks must be run in sequence");
+        let slot = std::mem::take(&mut self.slots[tick.0 as usize % SLOT_COUNT]);
+        self.next_tick = tick.0 + 1;
+        let mut due = Vec::new();
+        for mut entry in slot {
+            if entry.rounds == 0 {
+                due.push(entry);
+            } else {
+                entry.rounds -= 1;
+                self.slots[tick.0 as usize % SLOT_COUNT].push(entry);
+            }
+        }
+        // fire in scheduling order
+        due.sort_by_key(|entry| entry.id);
+        for entry in due {
+            fired.push(Fired {
+                id: entry.id,
+                callback: entry.callback.clone(),
+            });
+            if let Some(interval) = entry.interval {
+                self.insert(tick.0 + interval, entry);
+            }
+        }
+        let count = self
+            .clock_timers
+            .partition_point(|(instant, _, _)| *instant <= now);
+        for (_, id, callback) in self.clock_timers.drain(..count) {
+            fired.push(Fired { id, callback });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the ticks up to `end` (excluded), returns the timers fired with their tick
+    fn run(wheel: &mut TimerWheel, end: u64, now: time::Instant) -> Vec<(u64, TimerId)> {
+        let mut fired = Vec::new();
+        let mut result = Vec::new();
+        for tick in wheel.next_tick..end {
+            wheel.advance(Tick(tick), now, &mut fired);
+            result.extend(fired.drain(..).map(|f| (tick, f.id)));
+        }
+        result
+    }
+
+    #[test]
+    fn after_fires_once() {
+        let now = time::Instant::now();
+        let mut wheel = TimerWheel::new();
+        run(&mut wheel, 10, now);
+        // tick 9 was the last one run
+        let next = wheel.after_ticks(0);
+        let one = wheel.after_ticks(1);
+        let far = wheel.after_ticks(1000);
+        assert_eq!(wheel.len(), 3);
+        assert_eq!(
+            run(&mut wheel, 2000, now),
+            [(10, next), (10, one), (1009, far)]
+        );
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn every_fires_periodically() {
+        let now = time::Instant::now();
+        for &period in &[
+            1,
+            7,
+            SLOT_COUNT as u64,
+            SLOT_COUNT as u64 + 1,
+            3 * SLOT_COUNT as u64 + 5,
+        ] {
+            let mut wheel = TimerWheel::new();
+            let id = wheel.every_ticks(period);
+            let fired = run(&mut wheel, 10 * period, now);
+            let expected: Vec<_> = (1..=10).map(|i| (i * period - 1, id)).collect();
+            assert_eq!(fired, expected, "period {}", period);
+            assert_eq!(wheel.len(), 1);
+        }
+    }
+
+    #[test]
+    fn cancelled_timers_do_not_fire() {
+        let now = time::Instant::now();
+        let mut wheel = TimerWheel::new();
+        let once = wheel.after_ticks(5);
+        let every = wheel.every_ticks(3);
+        let clock = wheel.at(now);
+        let kept = wheel.after_ticks(300);
+        assert!(wheel.cancel(once));
+        assert!(wheel.cancel(clock));
+        assert!(!wheel.cancel(once));
+
+        assert_eq!(run(&mut wheel, 6, now), [(2, every), (5, every)]);
+        assert!(wheel.cancel(every));
+        assert_eq!(run(&mut wheel, 400, now), [(299, kept)]);
+        assert!(!wheel.cancel(kept));
+    }
+
+    #[test]
+    fn rebase_keeps_the_remaining_delay() {
+        let now = time::Instant::now();
+        let mut wheel = TimerWheel::new();
+        run(&mut wheel, 100, now);
+        let near = wheel.after_ticks(10);
+        let far = wheel.after_ticks(600);
+        let every = wheel.every_ticks(300);
+        assert_eq!(run(&mut wheel, 150, now), [(109, near)]);
+        // rewound 100 ticks: the timers keep firing relative to tick 150
+        wheel.rebase(50);
+        assert_eq!(
+            run(&mut wheel, 700, now),
+            [(50 + 249, every), (50 + 549, far), (50 + 549, every)]
+        );
+    }
+
+    #[test]
+    fn clock_timers_fire_in_time_order() {
+        let start = time::Instant::now();
+        let second = |n| start + time::Duration::from_secs(n);
+        let mut wheel = TimerWheel::new();
+        let late = wheel.at(second(3));
+        let early = wheel.at(second(1));
+        let also_early = wheel.at(second(1));
+        let middle = wheel.at(second(2));
+
+        assert!(run(&mut wheel, 1, start).is_empty());
+        assert_eq!(run(&mut wheel, 2, second(1)), [(1, early), (1, also_early)]);
+        assert_eq!(run(&mut wheel, 3, second(10)), [(2, middle), (2, late)]);
+        assert!(wheel.is_empty());
+    }
+}
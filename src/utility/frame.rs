@@ -84,19 +84,44 @@ impl fmt::Display for FrameCount {
 
 //const DEFAULT_TARGET_FPS: u32 = 240;
 
+// used when the monitor refresh rate is unknown
+const DEFAULT_REFRESH_RATE_MILLIHERTZ: u32 = 60_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TargetFrameRate {
     Unlimited,
     FramePerSeconds(u32),
+    /// Fractional frame rate, in millihertz (i.e. `Millihertz(59_940)` for 59.94 fps).
+    Millihertz(u32),
+    /// The refresh rate of the monitor the window is on.
+    MatchMonitor,
+    /// A fraction of the refresh rate of the monitor the window is on
+    /// (i.e. `Divisor(2)` for 30 fps on a 60 Hz monitor).
+    Divisor(u32),
 }
 
 impl TargetFrameRate {
-    pub fn target_frame_duration(&self) -> time::Duration {
-        match self {
-            TargetFrameRate::Unlimited => time::Duration::from_millis(0),
-            TargetFrameRate::FramePerSeconds(fps) => {
-                time::Duration::from_nanos((NANOS_PER_SEC as f32 / *fps as f32) as u64)
-            }
+    /// Returns the target frame duration (zero if unlimited).
+    ///
+    /// The monitor refresh rate is used by `MatchMonitor` and `Divisor`,
+    /// 60 Hz is assumed if it is unknown.
+    pub fn target_frame_duration(
+        &self,
+        monitor_refresh_rate_millihertz: Option<u32>,
+    ) -> time::Duration {
+        let refresh_rate =
+            monitor_refresh_rate_millihertz.unwrap_or(DEFAULT_REFRESH_RATE_MILLIHERTZ);
+        let millihertz = match *self {
+            TargetFrameRate::Unlimited => return time::Duration::from_millis(0),
+            TargetFrameRate::FramePerSeconds(fps) => fps.saturating_mul(1000),
+            TargetFrameRate::Millihertz(millihertz) => millihertz,
+            TargetFrameRate::MatchMonitor => refresh_rate,
+            TargetFrameRate::Divisor(n) => refresh_rate / n.max(1),
+        };
+        if millihertz == 0 {
+            return time::Duration::from_millis(0);
         }
+        time::Duration::from_nanos(NANOS_PER_SEC as u64 * 1000 / millihertz as u64)
     }
 }
 
@@ -112,6 +137,8 @@ trait FrameRateT<T: TFrameRate> {
 
 pub struct FrameRateThrottle {
     target_frame_rate: TargetFrameRate,
+    monitor_refresh_rate_millihertz: Option<u32>,
+    target_frame_duration: time::Duration,
     started: bool,
     //last_frame_time: time::Instant,
     next_frame_time: time::Instant,
@@ -135,6 +162,8 @@ impl FrameRateThrottle {
         let target_frame_rate = TargetFrameRate::Unlimited;
         FrameRateThrottle {
             target_frame_rate,
+            monitor_refresh_rate_millihertz: None,
+            target_frame_duration: time::Duration::from_millis(0),
             started: false,
             //last_frame_time: time::Instant::now(),
             next_frame_time: time::Instant::now(),
@@ -144,6 +173,30 @@ impl FrameRateThrottle {
 
     pub fn set_target_frame_rate(&mut self, target_frame_rate: TargetFrameRate) {
         self.target_frame_rate = target_frame_rate;
+        self.update_target_frame_duration();
+    }
+
+    /// Sets the refresh rate of the monitor the window is on
+    /// (to be called again when the window moves to another monitor).
+    pub fn set_monitor_refresh_rate(&mut self, refresh_rate_millihertz: Option<u32>) {
+        if self.monitor_refresh_rate_millihertz != refresh_rate_millihertz {
+            self.monitor_refresh_rate_millihertz = refresh_rate_millihertz;
+            self.update_target_frame_duration();
+        }
+    }
+
+    pub fn target_frame_duration(&self) -> time::Duration {
+        self.target_frame_duration
+    }
+
+    fn update_target_frame_duration(&mut self) {
+        self.target_frame_duration = self
+            .target_frame_rate
+            .target_frame_duration(self.monitor_refresh_rate_millihertz);
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.target_frame_duration == time::Duration::from_millis(0)
     }
 
     fn start(&mut self) {
@@ -155,7 +208,7 @@ impl FrameRateThrottle {
     }
 
     pub fn frame(&mut self) {
-        if let TargetFrameRate::Unlimited = self.target_frame_rate {
+        if self.is_unlimited() {
             return;
         }
         if !self.started {
@@ -169,7 +222,7 @@ impl FrameRateThrottle {
             return;
         }
         let frame_duration = now - self.next_frame_time;
-        let target_frame_duration = self.target_frame_rate.target_frame_duration();
+        let target_frame_duration = self.target_frame_duration;
         if frame_duration > target_frame_duration {
             let dt = frame_duration - target_frame_duration;
             println!("*** LATE (lag={:?})", dt);
@@ -188,7 +241,7 @@ impl FrameRateThrottle {
     }
 
     pub fn wait_until(&self) -> Option<time::Instant> {
-        if let TargetFrameRate::Unlimited = self.target_frame_rate {
+        if self.is_unlimited() {
             return None;
         }
         if self.wait {
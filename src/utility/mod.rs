@@ -1,2 +1,6 @@
 pub mod frame;
-//pub mod timer;
+pub mod resize;
+pub mod shutdown;
+pub mod step;
+pub mod timer;
+pub mod triple_buffer;
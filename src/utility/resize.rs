@@ -0,0 +1,183 @@
+use std::time;
+
+use winit::dpi::PhysicalSize;
+
+// ResizePolicy
+
+/// How bursts of `WindowEvent::Resized` events are turned into `Game::resized` notifications.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizePolicy {
+    /// Notify on every resize event that changes the size.
+    Immediate,
+    /// Notify at most once per frame, with the last size received during that frame.
+    #[default]
+    Coalesce,
+    /// Notify once no resize event has been received for the given duration
+    /// (i.e. when an interactive drag settles).
+    Debounce(time::Duration),
+}
+
+// ResizeCoalescer
+
+pub struct ResizeCoalescer {
+    policy: ResizePolicy,
+    // last size notified (or initial size)
+    size: Option<PhysicalSize<u32>>,
+    // size waiting to be notified
+    pending: Option<PhysicalSize<u32>>,
+    last_event_time: Option<time::Instant>,
+}
+
+impl Default for ResizeCoalescer {
+    fn default() -> Self {
+        Self::new(ResizePolicy::default())
+    }
+}
+
+impl ResizeCoalescer {
+    pub fn new(policy: ResizePolicy) -> ResizeCoalescer {
+        ResizeCoalescer {
+            policy,
+            size: None,
+            pending: None,
+            last_event_time: None,
+        }
+    }
+
+    pub fn policy(&self) -> ResizePolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ResizePolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the last size notified.
+    pub fn size(&self) -> Option<PhysicalSize<u32>> {
+        self.size
+    }
+
+    /// Sets the current size without notifying it (used for the initial size).
+    pub fn set_size(&mut self, size: PhysicalSize<u32>) {
+        self.size = Some(size);
+        self.pending = None;
+        self.last_event_time = None;
+    }
+
+    /// Records a resize event.
+    /// Returns the size to notify right away (`Immediate` policy only).
+    pub fn resized(
+        &mut self,
+        size: PhysicalSize<u32>,
+        now: time::Instant,
+    ) -> Option<PhysicalSize<u32>> {
+        self.last_event_time = Some(now);
+        if self.size == Some(size) {
+            // spurious event, or the size went back to the notified one
+            self.pending = None;
+            return None;
+        }
+        self.pending = Some(size);
+        match self.policy {
+            ResizePolicy::Immediate => self.take(),
+            ResizePolicy::Coalesce | ResizePolicy::Debounce(_) => None,
+        }
+    }
+
+    /// Returns the size to notify for this frame, if any.
+    pub fn poll(&mut self, now: time::Instant) -> Option<PhysicalSize<u32>> {
+        match self.wait_until() {
+            Some(instant) if now < instant => None,
+            _ => self.take(),
+        }
+    }
+
+    /// Returns when a pending resize will be due (`Debounce` policy only).
+    pub fn wait_until(&self) -> Option<time::Instant> {
+        match (self.policy, self.pending, self.last_event_time) {
+            (ResizePolicy::Debounce(delay), Some(_), Some(last_event_time)) => {
+                Some(last_event_time + delay)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn take(&mut self) -> Option<PhysicalSize<u32>> {
+        let size = self.pending.take();
+        if size.is_some() {
+            self.size = size;
+        }
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32) -> PhysicalSize<u32> {
+        PhysicalSize::new(width, height)
+    }
+
+    #[test]
+    fn immediate_notifies_every_change() {
+        let now = time::Instant::now();
+        let mut coalescer = ResizeCoalescer::new(ResizePolicy::Immediate);
+        coalescer.set_size(size(800, 600));
+        assert_eq!(coalescer.resized(size(800, 600), now), None);
+        assert_eq!(coalescer.resized(size(810, 600), now), Some(size(810, 600)));
+        assert_eq!(coalescer.resized(size(820, 600), now), Some(size(820, 600)));
+        assert_eq!(coalescer.poll(now), None);
+    }
+
+    #[test]
+    fn coalesce_notifies_the_last_size_once_per_frame() {
+        let now = time::Instant::now();
+        let mut coalescer = ResizeCoalescer::new(ResizePolicy::Coalesce);
+        coalescer.set_size(size(800, 600));
+        for width in [810, 820, 830] {
+            assert_eq!(coalescer.resized(size(width, 600), now), None);
+        }
+        assert!(coalescer.is_pending());
+        assert_eq!(coalescer.wait_until(), None);
+        assert_eq!(coalescer.poll(now), Some(size(830, 600)));
+        assert_eq!(coalescer.size(), Some(size(830, 600)));
+        assert_eq!(coalescer.poll(now), None);
+
+        // back to the notified size within a frame: nothing to notify
+        coalescer.resized(size(900, 700), now);
+        coalescer.resized(size(830, 600), now);
+        assert_eq!(coalescer.poll(now), None);
+    }
+
+    #[test]
+    fn debounce_waits_for_the_resizes_to_settle() {
+        let delay = time::Duration::from_millis(100);
+        let start = time::Instant::now();
+        let mut coalescer = ResizeCoalescer::new(ResizePolicy::Debounce(delay));
+        coalescer.set_size(size(800, 600));
+        coalescer.resized(size(810, 600), start);
+        let last = start + time::Duration::from_millis(50);
+        coalescer.resized(size(820, 600), last);
+        // each event pushes the deadline back
+        assert_eq!(coalescer.wait_until(), Some(last + delay));
+        assert_eq!(coalescer.poll(start + delay), None);
+        assert_eq!(coalescer.poll(last + delay), Some(size(820, 600)));
+        assert_eq!(coalescer.wait_until(), None);
+        assert!(!coalescer.is_pending());
+    }
+
+    #[test]
+    fn set_size_drops_the_pending_resize() {
+        let now = time::Instant::now();
+        let mut coalescer = ResizeCoalescer::new(ResizePolicy::Coalesce);
+        coalescer.resized(size(640, 480), now);
+        coalescer.set_size(size(1024, 768));
+        assert_eq!(coalescer.poll(now), None);
+        assert_eq!(coalescer.size(), Some(size(1024, 768)));
+    }
+}
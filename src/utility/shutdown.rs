@@ -0,0 +1,186 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Mutex;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+// set once the loop shut down (the Windows console close handler waits for it)
+static COMPLETED: AtomicBool = AtomicBool::new(false);
+// the actions replaced by the handler, restored once the shutdown is complete
+#[cfg(unix)]
+static PREVIOUS_ACTIONS: Mutex<Vec<(libc::c_int, libc::sigaction)>> = Mutex::new(Vec::new());
+
+/// Returns true once a shutdown was requested (by a signal or by `request`).
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Tells the handler the shutdown is complete (the game was destroyed)
+/// and restores the previous handlers.
+pub fn complete() {
+    COMPLETED.store(true, Ordering::SeqCst);
+    uninstall_handler();
+}
+
+// a new loop starts, previous requests are forgotten
+fn reset() {
+    REQUESTED.store(false, Ordering::SeqCst);
+    COMPLETED.store(false, Ordering::SeqCst);
+}
+
+/// Requests a shutdown on SIGINT and SIGTERM (Ctrl+C and console close on Windows),
+/// instead of terminating the process, until `complete` restores the previous handlers.
+/// Clears the previous requests.
+#[cfg(unix)]
+pub fn install_handler() -> io::Result<()> {
+    use std::mem;
+
+    extern "C" fn handle_signal(_signal: libc::c_int) {
+        // only async-signal-safe operations here
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+    reset();
+    let mut previous_actions = PREVIOUS_ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the actions are initialized before use, the handler is async-signal-safe
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous_action: libc::sigaction = mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous_action) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // when installed again, the actions from before the first install are kept
+            if !previous_actions.iter().any(|(other, _)| *other == signal) {
+                previous_actions.push((signal, previous_action));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn uninstall_handler() {
+    let mut previous_actions = PREVIOUS_ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    for (signal, action) in previous_actions.drain(..) {
+        // SAFETY: the action was returned by sigaction
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            eprintln!(
+                "failed to restore signal handler ({})",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Requests a shutdown on SIGINT and SIGTERM (Ctrl+C and console close on Windows),
+/// instead of terminating the process, until `complete` restores the previous handlers.
+/// Clears the previous requests.
+///
+/// Windows terminates the process as soon as the console close handler returns,
+/// so it waits (up to the 5 s the system allows) until `complete` is called.
+#[cfg(target_os = "windows")]
+pub fn install_handler() -> io::Result<()> {
+    use winapi::shared::minwindef::{FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+    reset();
+    // registered once, even when installed again
+    unsafe { SetConsoleCtrlHandler(Some(handle_control), FALSE) };
+    if unsafe { SetConsoleCtrlHandler(Some(handle_control), TRUE) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+unsafe extern "system" fn handle_control(
+    control_type: winapi::shared::minwindef::DWORD,
+) -> winapi::shared::minwindef::BOOL {
+    use std::{thread, time};
+    use winapi::shared::minwindef::{FALSE, TRUE};
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT};
+
+    match control_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            REQUESTED.store(true, Ordering::SeqCst);
+            TRUE
+        }
+        CTRL_CLOSE_EVENT => {
+            REQUESTED.store(true, Ordering::SeqCst);
+            // runs on its own thread, the loop keeps running meanwhile
+            let start_time = time::Instant::now();
+            while !COMPLETED.load(Ordering::SeqCst)
+                && start_time.elapsed() < time::Duration::from_secs(5)
+            {
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn uninstall_handler() {
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+    // the close handler returns once complete, then the process terminates
+    unsafe { SetConsoleCtrlHandler(Some(handle_control), FALSE) };
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+fn uninstall_handler() {}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+pub fn install_handler() -> io::Result<()> {
+    reset();
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "shutdown signals are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Serializes the tests using the process-wide shutdown state.
+    pub(crate) static SHUTDOWN_STATE: Mutex<()> = Mutex::new(());
+
+    #[cfg(unix)]
+    fn action(signal: libc::c_int) -> libc::sighandler_t {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            assert_eq!(libc::sigaction(signal, std::ptr::null(), &mut action), 0);
+            action.sa_sigaction
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signals_request_until_complete() {
+        let _lock = SHUTDOWN_STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = [action(libc::SIGINT), action(libc::SIGTERM)];
+        install_handler().unwrap();
+        // installing again keeps the original handler to restore
+        install_handler().unwrap();
+        assert!(!is_requested());
+        unsafe { libc::raise(libc::SIGINT) };
+        assert!(is_requested());
+        complete();
+        assert_eq!([action(libc::SIGINT), action(libc::SIGTERM)], previous);
+        install_handler().unwrap();
+        assert!(!is_requested());
+        complete();
+        assert_eq!([action(libc::SIGINT), action(libc::SIGTERM)], previous);
+    }
+}
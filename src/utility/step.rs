@@ -0,0 +1,104 @@
+use std::time;
+
+// number of consecutive frames before changing the update period
+const HYSTERESIS_FRAMES: u32 = 30;
+// frame cost smoothing factor
+const SMOOTHING: f64 = 0.1;
+// the update period is increased above this frame cost (relative to the frame budget)
+const HIGH_LOAD: f64 = 0.9;
+// the update period is decreased below this frame cost (relative to the frame budget)
+const LOW_LOAD: f64 = 0.4;
+
+// AdaptiveStep
+
+/// Adapts the fixed update period, within bounds, to the measured frame cost
+/// and to the display refresh rate.
+///
+/// The update period follows the display refresh rate when the machine keeps up,
+/// and is doubled (up to `max_period`) while frames are too expensive.
+pub struct AdaptiveStep {
+    min_period: time::Duration,
+    max_period: time::Duration,
+    period: time::Duration,
+    display_period: Option<time::Duration>,
+    // smoothed frame cost (in seconds)
+    frame_cost: Option<f64>,
+    // consecutive frames asking for a longer (> 0) or shorter (< 0) period
+    pressure: i32,
+}
+
+impl AdaptiveStep {
+    /// Creates an adaptive step starting at `period` (clamped to the bounds).
+    pub fn new(
+        period: time::Duration,
+        min_period: time::Duration,
+        max_period: time::Duration,
+    ) -> AdaptiveStep {
+        assert!(min_period <= max_period, "invalid update period bounds");
+        let period = period.clamp(min_period, max_period);
+        AdaptiveStep {
+            min_period,
+            max_period,
+            period,
+            display_period: None,
+            frame_cost: None,
+            pressure: 0,
+        }
+    }
+
+    pub fn period(&self) -> time::Duration {
+        self.period
+    }
+
+    /// Sets the refresh rate of the monitor the window is on.
+    pub fn set_display_refresh_rate(&mut self, refresh_rate_millihertz: Option<u32>) {
+        self.display_period = refresh_rate_millihertz
+            .filter(|&millihertz| millihertz > 0)
+            .map(|millihertz| time::Duration::from_nanos(1_000_000_000_000 / millihertz as u64));
+    }
+
+    /// The shortest period worth running: one step per displayed frame.
+    fn ideal_period(&self) -> time::Duration {
+        self.display_period
+            .unwrap_or(self.min_period)
+            .clamp(self.min_period, self.max_period)
+    }
+
+    /// Measures a frame (the time spent updating and rendering)
+    /// and returns the new update period if it changed.
+    pub fn frame(&mut self, frame_cost: time::Duration) -> Option<time::Duration> {
+        let cost = frame_cost.as_secs_f64();
+        let cost = match self.frame_cost {
+            Some(smoothed) => smoothed + SMOOTHING * (cost - smoothed),
+            None => cost,
+        };
+        self.frame_cost = Some(cost);
+
+        // the frame budget is the display period (or the update period if unknown)
+        let budget = self.display_period.unwrap_or(self.period).as_secs_f64();
+        let ideal_period = self.ideal_period();
+        if cost > HIGH_LOAD * budget && self.period < self.max_period {
+            self.pressure = self.pressure.max(0) + 1;
+        } else if (cost < LOW_LOAD * budget && self.period > ideal_period)
+            || self.period < ideal_period
+        {
+            self.pressure = self.pressure.min(0) - 1;
+        } else {
+            self.pressure = 0;
+        }
+
+        let period = if self.pressure >= HYSTERESIS_FRAMES as i32 {
+            (self.period * 2).min(self.max_period)
+        } else if self.pressure <= -(HYSTERESIS_FRAMES as i32) {
+            (self.period / 2).max(ideal_period)
+        } else {
+            return None;
+        };
+        self.pressure = 0;
+        if period == self.period {
+            return None;
+        }
+        self.period = period;
+        Some(period)
+    }
+}
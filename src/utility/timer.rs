@@ -1,13 +1,43 @@
+#[cfg(target_os = "windows")]
 use std::cmp;
+#[cfg(target_os = "windows")]
 use std::convert::TryInto;
+#[cfg(target_os = "windows")]
 use std::mem;
+use std::thread;
 use std::time;
 
+#[cfg(target_os = "windows")]
 use winapi::um::mmsystem;
+#[cfg(target_os = "windows")]
 use winapi::um::timeapi;
 
+// the OS may oversleep by up to this much, the rest of the wait is spent spinning
+const SPIN_MARGIN: time::Duration = time::Duration::from_millis(1);
+
+/// Sleeps until `deadline`, more accurately than `thread::sleep`.
+///
+/// The thread sleeps until shortly before the deadline, then yields until it is reached.
+/// On Windows, the timer resolution should be raised first (see `set_timer_max_resolution`).
+pub fn sleep_until(deadline: time::Instant) {
+    loop {
+        let now = time::Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let remaining = deadline - now;
+        if remaining > SPIN_MARGIN {
+            thread::sleep(remaining - SPIN_MARGIN);
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
 static mut CURRENT_PERIOD: Option<time::Duration> = None;
 
+#[cfg(target_os = "windows")]
 pub fn timer_resolution() -> (time::Duration, time::Duration) {
     unsafe {
         let mut time_caps = mmsystem::TIMECAPS {
@@ -38,11 +68,13 @@ pub fn timer_resolution() -> (time::Duration, time::Duration) {
     }
 }
 
+#[cfg(target_os = "windows")]
 pub fn set_timer_max_resolution() -> time::Duration {
     let (min_period, _) = timer_resolution();
     set_timer_resolution(min_period)
 }
 
+#[cfg(target_os = "windows")]
 pub fn set_timer_resolution(target_period: time::Duration) -> time::Duration {
     unsafe {
         if CURRENT_PERIOD.is_some() {
@@ -66,6 +98,7 @@ pub fn set_timer_resolution(target_period: time::Duration) -> time::Duration {
     }
 }
 
+#[cfg(target_os = "windows")]
 pub fn reset_timer_resolution() {
     unsafe {
         let period = CURRENT_PERIOD.expect("unbalanced timer resolution change");
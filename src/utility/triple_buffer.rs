@@ -0,0 +1,122 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const INDEX_MASK: usize = 0b11;
+// set when the middle slot holds a value the reader has not seen yet
+const FRESH: usize = 0b100;
+
+// TripleBuffer
+
+// the writer and the reader each own a slot, and exchange it with the middle slot
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    middle: AtomicUsize,
+}
+
+// each slot is only accessed by the side owning it
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    back: usize,
+}
+
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    front: usize,
+}
+
+/// Creates a lock-free single producer, single consumer buffer holding the latest value:
+/// writing never waits for the reader and reading never waits for the writer.
+///
+/// The reader sees `initial` until the first write.
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let shared = Arc::new(Shared {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        middle: AtomicUsize::new(1),
+    });
+    let writer = Writer {
+        shared: shared.clone(),
+        back: 2,
+    };
+    let reader = Reader { shared, front: 0 };
+    (writer, reader)
+}
+
+impl<T> Writer<T> {
+    /// Publishes a value (replacing the previous one if it was not read).
+    pub fn write(&mut self, value: T) {
+        // SAFETY: the back slot is owned by the writer
+        unsafe {
+            *self.shared.slots[self.back].get() = value;
+        }
+        let middle = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = middle & INDEX_MASK;
+    }
+}
+
+impl<T> Reader<T> {
+    /// Returns true if a value was published since the last read.
+    pub fn has_update(&self) -> bool {
+        self.shared.middle.load(Ordering::Relaxed) & FRESH != 0
+    }
+
+    /// Returns the latest published value.
+    pub fn read(&mut self) -> &T {
+        if self.has_update() {
+            let middle = self.shared.middle.swap(self.front, Ordering::AcqRel);
+            self.front = middle & INDEX_MASK;
+        }
+        // SAFETY: the front slot is owned by the reader
+        unsafe { &*self.shared.slots[self.front].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time;
+
+    #[test]
+    fn read_returns_the_last_value() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert!(!reader.has_update());
+        assert_eq!(*reader.read(), 0);
+        writer.write(1);
+        writer.write(2);
+        assert!(reader.has_update());
+        assert_eq!(*reader.read(), 2);
+        assert!(!reader.has_update());
+        assert_eq!(*reader.read(), 2);
+        writer.write(3);
+        assert_eq!(*reader.read(), 3);
+        assert_eq!(*reader.read(), 3);
+    }
+
+    #[test]
+    fn reader_sees_complete_increasing_values() {
+        const LAST: u64 = 100_000;
+        let (mut writer, mut reader) = triple_buffer(vec![0u64; 64]);
+        let thread = thread::spawn(move || {
+            for value in 1..=LAST {
+                writer.write(vec![value; 64]);
+            }
+        });
+        let deadline = time::Instant::now() + time::Duration::from_secs(10);
+        let mut last = 0;
+        while last < LAST {
+            let values = reader.read();
+            assert!(values.iter().all(|v| *v == values[0]), "torn value");
+            assert!(values[0] >= last, "{} read after {}", values[0], last);
+            last = values[0];
+            assert!(time::Instant::now() < deadline, "last value not read");
+        }
+        thread.join().unwrap();
+    }
+}
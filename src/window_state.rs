@@ -0,0 +1,179 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Window, WindowBuilder};
+
+// WindowState
+
+/// Window geometry and state, saved between runs.
+///
+/// Position and size are the windowed geometry (they are not updated
+/// while the window is maximized or fullscreen).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowState {
+    pub position: Option<PhysicalPosition<i32>>,
+    pub size: Option<PhysicalSize<u32>>,
+    pub maximized: bool,
+    pub fullscreen: bool,
+    /// Name of the monitor the window is on.
+    pub monitor: Option<String>,
+}
+
+impl WindowState {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<WindowState> {
+        let mut state = WindowState::default();
+        let mut x = None;
+        let mut y = None;
+        let mut width = None;
+        let mut height = None;
+        for line in fs::read_to_string(path)?.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            match key {
+                "x" => x = value.parse().ok(),
+                "y" => y = value.parse().ok(),
+                "width" => width = value.parse().ok(),
+                "height" => height = value.parse().ok(),
+                "maximized" => state.maximized = value == "true",
+                "fullscreen" => state.fullscreen = value == "true",
+                "monitor" => state.monitor = Some(value.to_string()),
+                // ignore unknown keys
+                _ => {}
+            }
+        }
+        if let (Some(x), Some(y)) = (x, y) {
+            state.position = Some(PhysicalPosition::new(x, y));
+        }
+        if let (Some(width), Some(height)) = (width, height) {
+            state.size = Some(PhysicalSize::new(width, height));
+        }
+        Ok(state)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut contents = String::new();
+        if let Some(position) = self.position {
+            contents += &format!("x={}\ny={}\n", position.x, position.y);
+        }
+        if let Some(size) = self.size {
+            contents += &format!("width={}\nheight={}\n", size.width, size.height);
+        }
+        contents += &format!("maximized={}\n", self.maximized);
+        contents += &format!("fullscreen={}\n", self.fullscreen);
+        if let Some(monitor) = &self.monitor {
+            contents += &format!("monitor={}\n", monitor);
+        }
+        fs::write(path, contents)
+    }
+
+    /// Updates the state from the window.
+    pub fn update(&mut self, window: &Window) {
+        self.maximized = window.is_maximized();
+        self.fullscreen = window.fullscreen().is_some();
+        if let Some(monitor) = window.current_monitor() {
+            self.monitor = monitor.name();
+        }
+        if !self.maximized && !self.fullscreen {
+            self.position = window.outer_position().ok();
+            self.size = Some(window.inner_size());
+        }
+    }
+
+    /// Moves the window to a monitor that still exists and makes it fit on it.
+    pub fn clamp(&mut self, monitors: &[MonitorHandle], primary_monitor: Option<&MonitorHandle>) {
+        let saved_monitor = monitors
+            .iter()
+            .find(|monitor| monitor.name().is_some() && monitor.name() == self.monitor);
+        let monitor = match saved_monitor
+            .or(primary_monitor)
+            .or_else(|| monitors.first())
+        {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        self.monitor = monitor.name();
+        let monitor_position = monitor.position();
+        let monitor_size = monitor.size();
+        if let Some(size) = &mut self.size {
+            size.width = size.width.min(monitor_size.width);
+            size.height = size.height.min(monitor_size.height);
+        }
+        if let Some(position) = &mut self.position {
+            let size = self.size.unwrap_or_default();
+            let max_x = monitor_position.x + (monitor_size.width - size.width) as i32;
+            let max_y = monitor_position.y + (monitor_size.height - size.height) as i32;
+            position.x = position.x.max(monitor_position.x).min(max_x);
+            position.y = position.y.max(monitor_position.y).min(max_y);
+        }
+    }
+
+    /// Applies the state to a window builder.
+    pub fn apply(&self, mut builder: WindowBuilder, monitors: &[MonitorHandle]) -> WindowBuilder {
+        if let Some(position) = self.position {
+            builder = builder.with_position(position);
+        }
+        if let Some(size) = self.size {
+            builder = builder.with_inner_size(size);
+        }
+        if self.fullscreen {
+            let monitor = monitors
+                .iter()
+                .find(|monitor| monitor.name().is_some() && monitor.name() == self.monitor)
+                .cloned();
+            builder = builder.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        }
+        builder.with_maximized(self.maximized)
+    }
+}
+
+// WindowStatePersister
+
+/// Restores the window state when the window is built
+/// and saves it when the game loop exits.
+pub struct WindowStatePersister {
+    path: PathBuf,
+    state: WindowState,
+}
+
+impl WindowStatePersister {
+    /// Loads the state saved by a previous run, if any.
+    pub fn new<P: AsRef<Path>>(path: P) -> WindowStatePersister {
+        let path = path.as_ref().to_path_buf();
+        let state = WindowState::load(&path).unwrap_or_default();
+        WindowStatePersister { path, state }
+    }
+
+    pub fn state(&self) -> &WindowState {
+        &self.state
+    }
+
+    pub(crate) fn restore(
+        &self,
+        builder: WindowBuilder,
+        monitors: &[MonitorHandle],
+        primary_monitor: Option<&MonitorHandle>,
+    ) -> WindowBuilder {
+        let mut state = self.state.clone();
+        state.clamp(monitors, primary_monitor);
+        state.apply(builder, monitors)
+    }
+
+    /// Tracks the windowed geometry (on move and resize).
+    pub(crate) fn track(&mut self, window: &Window) {
+        self.state.update(window);
+    }
+
+    pub(crate) fn save(&mut self, window: Option<&Window>) {
+        if let Some(window) = window {
+            self.state.update(window);
+        }
+        if let Err(e) = self.state.save(&self.path) {
+            eprintln!("failed to save window state to {:?} ({})", self.path, e);
+        }
+    }
+}